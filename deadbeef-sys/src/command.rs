@@ -0,0 +1,54 @@
+use crate::*;
+
+/// Control messages for the player, sent through DeadBeef's message pump.
+///
+/// The pump is thread safe, so these may be sent from any thread, the plugin's
+/// own included. It takes a lock though, so keep them out of realtime callbacks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlayerCommand {
+    Stop,
+    /// Start the current track, or resume it if paused.
+    PlayCurrent,
+    Pause,
+    TogglePause,
+    Next,
+    Prev,
+    PlayRandom,
+    /// Play the track at this index in the current playlist.
+    PlayNum(u32),
+    /// Seek the current track to this position in milliseconds.
+    Seek(u32),
+    /// Reopen the output plugin with its current settings.
+    ReinitSound,
+    /// Tell everyone one or more config items changed.
+    ConfigChanged,
+}
+
+impl PlayerCommand {
+    /// None of these carry a payload, so `ctx` is always 0.
+    fn to_raw(self) -> (u32, u32) {
+        match self {
+            Self::Stop => (DB_EV_STOP, 0),
+            Self::PlayCurrent => (DB_EV_PLAY_CURRENT, 0),
+            Self::Pause => (DB_EV_PAUSE, 0),
+            Self::TogglePause => (DB_EV_TOGGLE_PAUSE, 0),
+            Self::Next => (DB_EV_NEXT, 0),
+            Self::Prev => (DB_EV_PREV, 0),
+            Self::PlayRandom => (DB_EV_PLAY_RANDOM, 0),
+            Self::PlayNum(idx) => (DB_EV_PLAY_NUM, idx),
+            Self::Seek(ms) => (DB_EV_SEEK, ms),
+            Self::ReinitSound => (DB_EV_REINIT_SOUND, 0),
+            Self::ConfigChanged => (DB_EV_CONFIGCHANGED, 0),
+        }
+    }
+}
+
+impl DeadBeef {
+    pub fn send(command: PlayerCommand) -> Result<(), DB_Error> {
+        let (id, p1) = command.to_raw();
+        match unsafe { Self::sendmessage(id, 0, p1, 0) } {
+            0 => Ok(()),
+            _ => Err(DB_Error::QueueFull),
+        }
+    }
+}
@@ -0,0 +1,312 @@
+use crate::*;
+
+use std::ffi::{CStr, CString};
+use std::marker::PhantomData;
+
+/// Holds DeadBeef's config lock until dropped.
+///
+/// The lock is recursive, so the regular getters and setters can still be used
+/// while it is held, from the same thread.
+pub struct ConfLock {
+    // The lock belongs to the thread that took it
+    _not_send: PhantomData<*const ()>,
+}
+
+impl ConfLock {
+    /// Look a key up without a default, `None` if it isn't set.
+    pub fn get(&self, item: impl Into<String>) -> Option<String> {
+        let deadbeef = unsafe { DeadBeef::deadbeef() };
+
+        let item = LossyCString::new(item.into());
+        let conf_get_str_fast = deadbeef.get().conf_get_str_fast.unwrap();
+
+        let value = unsafe { conf_get_str_fast(item.as_ptr(), ptr::null()) };
+        if value.is_null() {
+            return None;
+        }
+        Some(unsafe { CStr::from_ptr(value) }.to_string_lossy().to_string())
+    }
+
+    pub fn contains(&self, item: impl Into<String>) -> bool {
+        self.get(item).is_some()
+    }
+
+    /// All items whose key starts with `prefix`, as `(key, value)` pairs.
+    pub fn find(&self, prefix: impl Into<String>) -> ConfItems<'_> {
+        ConfItems {
+            prefix: LossyCString::new(prefix.into()),
+            prev: ptr::null_mut(),
+            _lock: PhantomData,
+        }
+    }
+}
+
+impl Drop for ConfLock {
+    fn drop(&mut self) {
+        let deadbeef = unsafe { DeadBeef::deadbeef() };
+        let conf_unlock = deadbeef.get().conf_unlock.unwrap();
+
+        unsafe { conf_unlock(); }
+    }
+}
+
+/// Iterator returned by `ConfLock::find`, only valid while the lock is held.
+pub struct ConfItems<'a> {
+    prefix: CString,
+    prev: *mut DB_conf_item_t,
+    _lock: PhantomData<&'a ConfLock>,
+}
+
+impl Iterator for ConfItems<'_> {
+    type Item = (String, String);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let deadbeef = unsafe { DeadBeef::deadbeef() };
+        let conf_find = deadbeef.get().conf_find.unwrap();
+
+        let item = unsafe { conf_find(self.prefix.as_ptr(), self.prev) };
+        if item.is_null() {
+            return None;
+        }
+        self.prev = item;
+        let (key, value) = unsafe { ((*item).key, (*item).value) };
+        let to_string = |s: *const std::ffi::c_char| {
+            if s.is_null() {
+                String::new()
+            } else {
+                unsafe { CStr::from_ptr(s) }.to_string_lossy().to_string()
+            }
+        };
+        Some((to_string(key), to_string(value)))
+    }
+}
+
+impl DeadBeef {
+    pub fn conf_lock() -> ConfLock {
+        let deadbeef = unsafe { DeadBeef::deadbeef() };
+        let conf_lock = deadbeef.get().conf_lock.unwrap();
+
+        unsafe { conf_lock(); }
+        ConfLock { _not_send: PhantomData }
+    }
+
+    /// Like `conf_get_str`, but `None` for a missing key and no length limit.
+    pub fn conf_get(item: impl Into<String>) -> Option<String> {
+        Self::conf_lock().get(item)
+    }
+
+    pub fn conf_get_int(item: impl Into<String>) -> Option<i32> {
+        let deadbeef = unsafe { DeadBeef::deadbeef() };
+
+        let lock = Self::conf_lock();
+        let item = item.into();
+        if !lock.contains(item.as_str()) {
+            return None;
+        }
+        let item = LossyCString::new(item);
+        let conf_get_int = deadbeef.get().conf_get_int.unwrap();
+
+        Some(unsafe { conf_get_int(item.as_ptr(), 0) })
+    }
+
+    pub fn conf_get_int64(item: impl Into<String>) -> Option<i64> {
+        let deadbeef = unsafe { DeadBeef::deadbeef() };
+
+        let lock = Self::conf_lock();
+        let item = item.into();
+        if !lock.contains(item.as_str()) {
+            return None;
+        }
+        let item = LossyCString::new(item);
+        let conf_get_int64 = deadbeef.get().conf_get_int64.unwrap();
+
+        Some(unsafe { conf_get_int64(item.as_ptr(), 0) })
+    }
+
+    pub fn conf_get_float(item: impl Into<String>) -> Option<f32> {
+        let deadbeef = unsafe { DeadBeef::deadbeef() };
+
+        let lock = Self::conf_lock();
+        let item = item.into();
+        if !lock.contains(item.as_str()) {
+            return None;
+        }
+        let item = LossyCString::new(item);
+        let conf_get_float = deadbeef.get().conf_get_float.unwrap();
+
+        Some(unsafe { conf_get_float(item.as_ptr(), 0.0) })
+    }
+
+    pub fn conf_set_int(item: impl Into<String>, value: i32) {
+        let deadbeef = unsafe { DeadBeef::deadbeef() };
+
+        let item = LossyCString::new(item.into());
+        let conf_set_int = deadbeef.get().conf_set_int.unwrap();
+
+        unsafe { conf_set_int(item.as_ptr(), value); }
+    }
+
+    pub fn conf_set_int64(item: impl Into<String>, value: i64) {
+        let deadbeef = unsafe { DeadBeef::deadbeef() };
+
+        let item = LossyCString::new(item.into());
+        let conf_set_int64 = deadbeef.get().conf_set_int64.unwrap();
+
+        unsafe { conf_set_int64(item.as_ptr(), value); }
+    }
+
+    pub fn conf_set_float(item: impl Into<String>, value: f32) {
+        let deadbeef = unsafe { DeadBeef::deadbeef() };
+
+        let item = LossyCString::new(item.into());
+        let conf_set_float = deadbeef.get().conf_set_float.unwrap();
+
+        unsafe { conf_set_float(item.as_ptr(), value); }
+    }
+
+    /// Remove every item whose key starts with `prefix`.
+    pub fn conf_remove_items(prefix: impl Into<String>) {
+        let deadbeef = unsafe { DeadBeef::deadbeef() };
+
+        let prefix = LossyCString::new(prefix.into());
+        let conf_remove_items = deadbeef.get().conf_remove_items.unwrap();
+
+        unsafe { conf_remove_items(prefix.as_ptr()); }
+    }
+
+    /// Write the config to disk now rather than whenever DeadBeef gets to it.
+    pub fn conf_save() -> bool {
+        let deadbeef = unsafe { DeadBeef::deadbeef() };
+        let conf_save = deadbeef.get().conf_save.unwrap();
+
+        unsafe { conf_save() == 0 }
+    }
+}
+
+/// A type that can be stored as a single config item.
+pub trait ConfValue: Sized {
+    fn conf_get(item: &str) -> Option<Self>;
+    fn conf_set(item: &str, value: &Self);
+}
+
+impl ConfValue for i32 {
+    fn conf_get(item: &str) -> Option<Self> {
+        DeadBeef::conf_get_int(item)
+    }
+    fn conf_set(item: &str, value: &Self) {
+        DeadBeef::conf_set_int(item, *value)
+    }
+}
+
+impl ConfValue for u32 {
+    fn conf_get(item: &str) -> Option<Self> {
+        DeadBeef::conf_get_int64(item).and_then(|v| u32::try_from(v).ok())
+    }
+    fn conf_set(item: &str, value: &Self) {
+        DeadBeef::conf_set_int64(item, *value as i64)
+    }
+}
+
+impl ConfValue for i64 {
+    fn conf_get(item: &str) -> Option<Self> {
+        DeadBeef::conf_get_int64(item)
+    }
+    fn conf_set(item: &str, value: &Self) {
+        DeadBeef::conf_set_int64(item, *value)
+    }
+}
+
+impl ConfValue for f32 {
+    fn conf_get(item: &str) -> Option<Self> {
+        DeadBeef::conf_get_float(item)
+    }
+    fn conf_set(item: &str, value: &Self) {
+        DeadBeef::conf_set_float(item, *value)
+    }
+}
+
+/// Stored as 0 or 1, the way checkbox properties are.
+impl ConfValue for bool {
+    fn conf_get(item: &str) -> Option<Self> {
+        DeadBeef::conf_get_int(item).map(|v| v != 0)
+    }
+    fn conf_set(item: &str, value: &Self) {
+        DeadBeef::conf_set_int(item, *value as i32)
+    }
+}
+
+impl ConfValue for String {
+    fn conf_get(item: &str) -> Option<Self> {
+        DeadBeef::conf_get(item)
+    }
+    fn conf_set(item: &str, value: &Self) {
+        DeadBeef::conf_set_str(item, value.as_str())
+    }
+}
+
+/// A group of settings stored under a common key prefix, usually declared
+/// with `conf_settings!`.
+pub trait ConfSettings: Sized {
+    /// Read every field from `<prefix><field>`, missing ones get their default.
+    fn load(prefix: &str) -> Self;
+    /// Write every field to `<prefix><field>`.
+    fn save(&self, prefix: &str);
+}
+
+/// Declare a settings struct along with its `ConfSettings` impl. Each field
+/// needs a type implementing `ConfValue` and a default for when it isn't set.
+///
+/// ```ignore
+/// conf_settings! {
+///     pub struct Settings {
+///         pub fade_ms: u32 = 30,
+///         pub soundcard: String = "default".into(),
+///     }
+/// }
+/// let settings = Settings::load("pipewirerust_");
+/// ```
+#[macro_export]
+macro_rules! conf_settings {
+    (
+        $(#[$meta:meta])*
+        $vis:vis struct $name:ident {
+            $(
+                $(#[$fmeta:meta])*
+                $fvis:vis $field:ident : $ty:ty = $default:expr
+            ),* $(,)?
+        }
+    ) => {
+        $(#[$meta])*
+        $vis struct $name {
+            $(
+                $(#[$fmeta])*
+                $fvis $field: $ty,
+            )*
+        }
+
+        impl $crate::ConfSettings for $name {
+            fn load(prefix: &str) -> Self {
+                // Read a consistent snapshot
+                let _lock = $crate::DeadBeef::conf_lock();
+                Self {
+                    $(
+                        $field: <$ty as $crate::ConfValue>::conf_get(
+                            &format!("{}{}", prefix, stringify!($field)),
+                        )
+                        .unwrap_or_else(|| $default),
+                    )*
+                }
+            }
+
+            fn save(&self, prefix: &str) {
+                let _lock = $crate::DeadBeef::conf_lock();
+                $(
+                    <$ty as $crate::ConfValue>::conf_set(
+                        &format!("{}{}", prefix, stringify!($field)),
+                        &self.$field,
+                    );
+                )*
+            }
+        }
+    };
+}
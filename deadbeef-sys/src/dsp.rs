@@ -0,0 +1,313 @@
+use crate::*;
+
+use std::ffi::{c_char, CStr};
+use std::marker::PhantomData;
+
+/// Buffer size DSP parameter values are read into at first, grown as needed
+/// up to `MAX_PARAM_LEN`.
+const INITIAL_PARAM_LEN: usize = 4096;
+const MAX_PARAM_LEN: usize = 1 << 20;
+
+unsafe fn copy_str(s: *const c_char) -> String {
+    if s.is_null() {
+        String::new()
+    } else {
+        CStr::from_ptr(s).to_string_lossy().to_string()
+    }
+}
+
+/// One DSP in a chain, borrowed from the chain it is in.
+#[derive(Clone, Copy)]
+pub struct DspNode<'a> {
+    ptr: ptr::NonNull<ddb_dsp_context_t>,
+    _chain: PhantomData<&'a ddb_dsp_context_t>,
+}
+
+impl<'a> DspNode<'a> {
+    fn plugin(&self) -> &'a DB_dsp_t {
+        // Every context is opened by, and points back to, its plugin
+        unsafe { &*(*self.ptr.as_ptr()).plugin }
+    }
+
+    pub fn plugin_id(&self) -> String {
+        unsafe { copy_str(self.plugin().plugin.id) }
+    }
+
+    pub fn name(&self) -> String {
+        unsafe { copy_str(self.plugin().plugin.name) }
+    }
+
+    pub fn enabled(&self) -> bool {
+        unsafe { (*self.ptr.as_ptr()).enabled() != 0 }
+    }
+
+    /// Takes effect on the active chain after `DeadBeef::streamer_dsp_refresh`.
+    pub fn set_enabled(&self, enabled: bool) {
+        unsafe { (*self.ptr.as_ptr()).set_enabled(enabled as u32) }
+    }
+
+    pub fn param_count(&self) -> usize {
+        match self.plugin().num_params {
+            Some(num_params) => unsafe { num_params() }.max(0) as usize,
+            None => 0,
+        }
+    }
+
+    /// Display name of parameter `p`.
+    pub fn param_name(&self, p: usize) -> Option<String> {
+        if p >= self.param_count() {
+            return None;
+        }
+        let get_param_name = self.plugin().get_param_name?;
+        Some(unsafe { copy_str(get_param_name(p as i32)) })
+    }
+
+    pub fn param(&self, p: usize) -> Option<String> {
+        if p >= self.param_count() {
+            return None;
+        }
+        let get_param = self.plugin().get_param?;
+
+        let mut buf: Vec<u8> = vec![0; INITIAL_PARAM_LEN];
+        loop {
+            unsafe { get_param(self.ptr.as_ptr(), p as i32, buf.as_mut_ptr() as *mut c_char, buf.len() as i32); }
+
+            let cstr = CStr::from_bytes_until_nul(&buf).ok()?;
+            // There is no length to go by, a full buffer may mean the value got cut short
+            if cstr.to_bytes().len() + 1 >= buf.len() && buf.len() < MAX_PARAM_LEN {
+                buf = vec![0; buf.len() * 2];
+                continue;
+            }
+            return Some(cstr.to_string_lossy().to_string());
+        }
+    }
+
+    /// Returns false if there is no such parameter.
+    pub fn set_param(&self, p: usize, value: impl Into<String>) -> bool {
+        if p >= self.param_count() {
+            return false;
+        }
+        let Some(set_param) = self.plugin().set_param else {
+            return false;
+        };
+
+        let value = LossyCString::new(value.into());
+        unsafe { set_param(self.ptr.as_ptr(), p as i32, value.as_ptr()); }
+        true
+    }
+
+    /// Every parameter as `(name, value)` pairs.
+    pub fn params(&self) -> Vec<(String, String)> {
+        (0..self.param_count())
+            .filter_map(|p| Some((self.param_name(p)?, self.param(p)?)))
+            .collect()
+    }
+
+    /// Whether the DSP leaves audio in `fmt` untouched with its current
+    /// settings, `None` if it can't tell.
+    pub fn can_bypass(&self, fmt: &WaveFormat) -> Option<bool> {
+        let can_bypass = self.plugin().can_bypass?;
+
+        let mut fmt: ddb_waveformat_t = (*fmt).into();
+        Some(unsafe { can_bypass(self.ptr.as_ptr(), &mut fmt) != 0 })
+    }
+
+    /// A fresh context from the same plugin with the same settings.
+    fn duplicate(&self) -> Option<ptr::NonNull<ddb_dsp_context_t>> {
+        let open = self.plugin().open?;
+
+        let ctx = ptr::NonNull::new(unsafe { open() })?;
+        let copy = DspNode { ptr: ctx, _chain: PhantomData };
+        for p in 0..self.param_count() {
+            if let Some(value) = self.param(p) {
+                copy.set_param(p, value);
+            }
+        }
+        copy.set_enabled(self.enabled());
+        Some(ctx)
+    }
+}
+
+impl std::fmt::Debug for DspNode<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DspNode")
+            .field("plugin_id", &self.plugin_id())
+            .field("enabled", &self.enabled())
+            .finish()
+    }
+}
+
+/// Iterator over the DSPs in a chain, in processing order.
+pub struct DspNodes<'a> {
+    next: *mut ddb_dsp_context_t,
+    _chain: PhantomData<&'a ddb_dsp_context_t>,
+}
+
+impl<'a> Iterator for DspNodes<'a> {
+    type Item = DspNode<'a>;
+
+    fn next(&mut self) -> Option<DspNode<'a>> {
+        let ptr = ptr::NonNull::new(self.next)?;
+        self.next = unsafe { (*ptr.as_ptr()).next };
+        Some(DspNode { ptr, _chain: PhantomData })
+    }
+}
+
+/// The chain the streamer is running, see `DeadBeef::streamer_get_dsp_chain`
+/// for how long it stays valid.
+pub struct ActiveDspChain {
+    head: *mut ddb_dsp_context_t,
+    _not_send: PhantomData<*const ()>,
+}
+
+impl ActiveDspChain {
+    pub fn iter(&self) -> DspNodes<'_> {
+        DspNodes { next: self.head, _chain: PhantomData }
+    }
+
+    /// A copy to rearrange and hand back with `DeadBeef::streamer_set_dsp_chain`.
+    pub fn to_chain(&self) -> DspChain {
+        let mut chain = DspChain::new();
+        for node in self.iter() {
+            if let Some(ctx) = node.duplicate() {
+                chain.append(ctx);
+            }
+        }
+        chain
+    }
+}
+
+/// A DSP chain of our own, with every DSP freed on drop.
+pub struct DspChain {
+    head: *mut ddb_dsp_context_t,
+}
+
+impl DspChain {
+    pub fn new() -> Self {
+        Self { head: ptr::null_mut() }
+    }
+
+    pub fn iter(&self) -> DspNodes<'_> {
+        DspNodes { next: self.head, _chain: PhantomData }
+    }
+
+    pub fn len(&self) -> usize {
+        self.iter().count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.head.is_null()
+    }
+
+    /// Open a DSP by plugin id and add it to the end of the chain.
+    pub fn push(&mut self, plugin_id: impl Into<String>) -> Result<DspNode<'_>, DB_Error> {
+        let deadbeef = unsafe { DeadBeef::deadbeef() };
+
+        let plugin_id = LossyCString::new(plugin_id.into());
+        let plug_get_for_id = deadbeef.get().plug_get_for_id.unwrap();
+
+        let plugin = unsafe { plug_get_for_id(plugin_id.as_ptr()) };
+        if plugin.is_null() || unsafe { (*plugin).type_ } != DB_PLUGIN_DSP as i32 {
+            return Err(DB_Error::NotFound);
+        }
+        let plugin = unsafe { &*(plugin as *const DB_dsp_t) };
+        let open = plugin.open.ok_or(DB_Error::CreationFailed)?;
+        let ctx = ptr::NonNull::new(unsafe { open() }).ok_or(DB_Error::CreationFailed)?;
+        self.append(ctx);
+        Ok(DspNode { ptr: ctx, _chain: PhantomData })
+    }
+
+    /// Take the DSP at `index` out of the chain and close it.
+    pub fn remove(&mut self, index: usize) -> bool {
+        let mut link: *mut *mut ddb_dsp_context_t = &mut self.head;
+        unsafe {
+            for _ in 0..index {
+                if (*link).is_null() {
+                    return false;
+                }
+                link = &mut (**link).next;
+            }
+            let ctx = *link;
+            if ctx.is_null() {
+                return false;
+            }
+            *link = (*ctx).next;
+            (*ctx).next = ptr::null_mut();
+            if let Some(close) = (*(*ctx).plugin).close {
+                close(ctx);
+            }
+        }
+        true
+    }
+
+    fn append(&mut self, ctx: ptr::NonNull<ddb_dsp_context_t>) {
+        let mut link: *mut *mut ddb_dsp_context_t = &mut self.head;
+        unsafe {
+            while !(*link).is_null() {
+                link = &mut (**link).next;
+            }
+            (*ctx.as_ptr()).next = ptr::null_mut();
+            *link = ctx.as_ptr();
+        }
+    }
+}
+
+impl Default for DspChain {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for DspChain {
+    fn drop(&mut self) {
+        if self.head.is_null() {
+            return;
+        }
+        let deadbeef = unsafe { DeadBeef::deadbeef() };
+        let dsp_preset_free = deadbeef.get().dsp_preset_free.unwrap();
+
+        unsafe { dsp_preset_free(self.head); }
+    }
+}
+
+impl DeadBeef {
+    /// # Safety
+    /// The chain belongs to the streamer and is freed whenever it is replaced, by
+    /// `streamer_set_dsp_chain` or by the user picking another preset. The returned
+    /// value must be dropped before that happens, which in practice means using it
+    /// on the main thread, where the UI replaces the chain, and not across a call
+    /// to `streamer_set_dsp_chain`.
+    pub unsafe fn streamer_get_dsp_chain() -> ActiveDspChain {
+        let deadbeef = DeadBeef::deadbeef();
+        let streamer_get_dsp_chain = deadbeef.get().streamer_get_dsp_chain.unwrap();
+
+        ActiveDspChain {
+            head: streamer_get_dsp_chain(),
+            _not_send: PhantomData,
+        }
+    }
+
+    /// Replace the active chain with a copy of `chain`.
+    pub fn streamer_set_dsp_chain(chain: &DspChain) {
+        let deadbeef = unsafe { DeadBeef::deadbeef() };
+        let streamer_set_dsp_chain = deadbeef.get().streamer_set_dsp_chain.unwrap();
+
+        unsafe { streamer_set_dsp_chain(chain.head); }
+    }
+
+    /// Apply parameter and enabled changes made to the active chain.
+    pub fn streamer_dsp_refresh() {
+        let deadbeef = unsafe { DeadBeef::deadbeef() };
+        let streamer_dsp_refresh = deadbeef.get().streamer_dsp_refresh.unwrap();
+
+        unsafe { streamer_dsp_refresh(); }
+    }
+
+    /// Store the active chain so it is restored on the next start.
+    pub fn streamer_dsp_chain_save() -> bool {
+        let deadbeef = unsafe { DeadBeef::deadbeef() };
+        let streamer_dsp_chain_save = deadbeef.get().streamer_dsp_chain_save.unwrap();
+
+        unsafe { streamer_dsp_chain_save() == 0 }
+    }
+}
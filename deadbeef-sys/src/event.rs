@@ -0,0 +1,199 @@
+use crate::*;
+
+/// A message from DeadBeef's message pump, decoded from the raw
+/// `(id, ctx, p1, p2)` a plugin's `message` callback receives.
+///
+/// Tracks are borrowed from the event payload and only live as long as the
+/// callback, clone one to keep it.
+#[derive(Debug)]
+pub enum DbEvent<'a> {
+    Next,
+    Prev,
+    PlayCurrent,
+    /// Play the track at this index in the current playlist.
+    PlayNum(u32),
+    Stop,
+    Pause,
+    PlayRandom,
+    Terminate,
+    PlaylistRefresh,
+    ReinitSound,
+    ConfigChanged,
+    TogglePause,
+    Activated,
+    /// The player was paused, or unpaused if false.
+    Paused(bool),
+    /// What changed, one of `DDB_PLAYLIST_CHANGE_*`.
+    PlaylistChanged(u32),
+    VolumeChanged,
+    OutputChanged,
+    PlaylistSwitched,
+    /// Seek the current track to this position in milliseconds.
+    Seek(u32),
+    ActionsChanged,
+    DspChainChanged,
+    SelChanged,
+    PluginsLoaded,
+    FocusSelection,
+    SongChanged {
+        from: Option<&'a PlItem>,
+        to: Option<&'a PlItem>,
+        /// Seconds `from` was playing for.
+        playtime: f32,
+        /// When `from` started playing, in seconds since the epoch.
+        started_timestamp: i64,
+    },
+    SongStarted {
+        track: Option<&'a PlItem>,
+        started_timestamp: i64,
+    },
+    SongFinished {
+        track: Option<&'a PlItem>,
+        /// Seconds the track was playing for.
+        playtime: f32,
+        started_timestamp: i64,
+    },
+    TrackInfoChanged {
+        track: Option<&'a PlItem>,
+    },
+    Seeked {
+        track: Option<&'a PlItem>,
+        /// New position in seconds.
+        playpos: f32,
+    },
+    TrackFocusCurrent,
+    CursorMoved {
+        /// `PL_MAIN` or `PL_SEARCH`.
+        iter: u32,
+        track: Option<&'a PlItem>,
+    },
+    /// Anything newer than these bindings.
+    Unknown {
+        id: u32,
+        ctx: usize,
+        p1: u32,
+        p2: u32,
+    },
+}
+
+impl<'a> DbEvent<'a> {
+    /// # Safety
+    /// The arguments must be exactly what DeadBeef passed to the `message`
+    /// callback, which must not have returned before `'a` ends.
+    pub unsafe fn decode(id: u32, ctx: usize, p1: u32, p2: u32) -> Self {
+        // A structured event without its payload, don't trust it
+        if id >= DB_EV_FIRST && id != DB_EV_TRACKFOCUSCURRENT && ctx == 0 {
+            return Self::Unknown { id, ctx, p1, p2 };
+        }
+        match id {
+            DB_EV_NEXT => Self::Next,
+            DB_EV_PREV => Self::Prev,
+            DB_EV_PLAY_CURRENT => Self::PlayCurrent,
+            DB_EV_PLAY_NUM => Self::PlayNum(p1),
+            DB_EV_STOP => Self::Stop,
+            DB_EV_PAUSE => Self::Pause,
+            DB_EV_PLAY_RANDOM => Self::PlayRandom,
+            DB_EV_TERMINATE => Self::Terminate,
+            DB_EV_PLAYLIST_REFRESH => Self::PlaylistRefresh,
+            DB_EV_REINIT_SOUND => Self::ReinitSound,
+            DB_EV_CONFIGCHANGED => Self::ConfigChanged,
+            DB_EV_TOGGLE_PAUSE => Self::TogglePause,
+            DB_EV_ACTIVATED => Self::Activated,
+            DB_EV_PAUSED => Self::Paused(p1 != 0),
+            DB_EV_PLAYLISTCHANGED => Self::PlaylistChanged(p1),
+            DB_EV_VOLUMECHANGED => Self::VolumeChanged,
+            DB_EV_OUTPUTCHANGED => Self::OutputChanged,
+            DB_EV_PLAYLISTSWITCHED => Self::PlaylistSwitched,
+            DB_EV_SEEK => Self::Seek(p1),
+            DB_EV_ACTIONSCHANGED => Self::ActionsChanged,
+            DB_EV_DSPCHAINCHANGED => Self::DspChainChanged,
+            DB_EV_SELCHANGED => Self::SelChanged,
+            DB_EV_PLUGINSLOADED => Self::PluginsLoaded,
+            DB_EV_FOCUS_SELECTION => Self::FocusSelection,
+            DB_EV_SONGCHANGED => {
+                let ev = &*(ctx as *const ddb_event_trackchange_t);
+                Self::SongChanged {
+                    from: PlItem::from_raw_ref(&ev.from),
+                    to: PlItem::from_raw_ref(&ev.to),
+                    playtime: ev.playtime,
+                    started_timestamp: ev.started_timestamp as i64,
+                }
+            }
+            DB_EV_SONGSTARTED => {
+                let ev = &*(ctx as *const ddb_event_track_t);
+                Self::SongStarted {
+                    track: PlItem::from_raw_ref(&ev.track),
+                    started_timestamp: ev.started_timestamp as i64,
+                }
+            }
+            DB_EV_SONGFINISHED => {
+                let ev = &*(ctx as *const ddb_event_track_t);
+                Self::SongFinished {
+                    track: PlItem::from_raw_ref(&ev.track),
+                    playtime: ev.playtime,
+                    started_timestamp: ev.started_timestamp as i64,
+                }
+            }
+            DB_EV_TRACKINFOCHANGED => {
+                let ev = &*(ctx as *const ddb_event_track_t);
+                Self::TrackInfoChanged {
+                    track: PlItem::from_raw_ref(&ev.track),
+                }
+            }
+            DB_EV_SEEKED => {
+                let ev = &*(ctx as *const ddb_event_playpos_t);
+                Self::Seeked {
+                    track: PlItem::from_raw_ref(&ev.track),
+                    playpos: ev.playpos,
+                }
+            }
+            // Not actually structured despite its id
+            DB_EV_TRACKFOCUSCURRENT => Self::TrackFocusCurrent,
+            DB_EV_CURSOR_MOVED => {
+                let ev = &*(ctx as *const ddb_event_track_t);
+                Self::CursorMoved {
+                    iter: p1,
+                    track: PlItem::from_raw_ref(&ev.track),
+                }
+            }
+            _ => Self::Unknown { id, ctx, p1, p2 },
+        }
+    }
+
+    pub fn id(&self) -> u32 {
+        match self {
+            Self::Next => DB_EV_NEXT,
+            Self::Prev => DB_EV_PREV,
+            Self::PlayCurrent => DB_EV_PLAY_CURRENT,
+            Self::PlayNum(_) => DB_EV_PLAY_NUM,
+            Self::Stop => DB_EV_STOP,
+            Self::Pause => DB_EV_PAUSE,
+            Self::PlayRandom => DB_EV_PLAY_RANDOM,
+            Self::Terminate => DB_EV_TERMINATE,
+            Self::PlaylistRefresh => DB_EV_PLAYLIST_REFRESH,
+            Self::ReinitSound => DB_EV_REINIT_SOUND,
+            Self::ConfigChanged => DB_EV_CONFIGCHANGED,
+            Self::TogglePause => DB_EV_TOGGLE_PAUSE,
+            Self::Activated => DB_EV_ACTIVATED,
+            Self::Paused(_) => DB_EV_PAUSED,
+            Self::PlaylistChanged(_) => DB_EV_PLAYLISTCHANGED,
+            Self::VolumeChanged => DB_EV_VOLUMECHANGED,
+            Self::OutputChanged => DB_EV_OUTPUTCHANGED,
+            Self::PlaylistSwitched => DB_EV_PLAYLISTSWITCHED,
+            Self::Seek(_) => DB_EV_SEEK,
+            Self::ActionsChanged => DB_EV_ACTIONSCHANGED,
+            Self::DspChainChanged => DB_EV_DSPCHAINCHANGED,
+            Self::SelChanged => DB_EV_SELCHANGED,
+            Self::PluginsLoaded => DB_EV_PLUGINSLOADED,
+            Self::FocusSelection => DB_EV_FOCUS_SELECTION,
+            Self::SongChanged { .. } => DB_EV_SONGCHANGED,
+            Self::SongStarted { .. } => DB_EV_SONGSTARTED,
+            Self::SongFinished { .. } => DB_EV_SONGFINISHED,
+            Self::TrackInfoChanged { .. } => DB_EV_TRACKINFOCHANGED,
+            Self::Seeked { .. } => DB_EV_SEEKED,
+            Self::TrackFocusCurrent => DB_EV_TRACKFOCUSCURRENT,
+            Self::CursorMoved { .. } => DB_EV_CURSOR_MOVED,
+            Self::Unknown { id, .. } => *id,
+        }
+    }
+}
@@ -7,7 +7,6 @@
 
 use lossycstring::LossyCString;
 
-use std::ffi::c_void;
 use std::ptr;
 use thiserror::Error;
 
@@ -19,6 +18,41 @@ mod api {
 include!(concat!(env!("OUT_DIR"), "/bindings.rs"));
 }
 pub use api::*;
+
+mod conf;
+pub use conf::*;
+
+mod titleformat;
+pub use titleformat::*;
+
+mod event;
+pub use event::*;
+
+mod command;
+pub use command::*;
+
+mod playitem;
+pub use playitem::*;
+
+mod playlist;
+pub use playlist::*;
+
+mod streamer;
+pub use streamer::*;
+
+mod waveformat;
+pub use waveformat::*;
+
+mod vis;
+pub use vis::*;
+
+mod dsp;
+pub use dsp::*;
+
+// Playlist iterators, `#define`s the bindings leave out
+pub const PL_MAIN: i32 = 0;
+pub const PL_SEARCH: i32 = 1;
+
 /// Main DeadBeef struct that encapsulates common DeadBeef API functions.
 pub struct DeadBeef {
     pub(crate) ptr: *const DB_functions_t,
@@ -45,6 +79,10 @@ pub enum DB_Error {
     CreationFailed,
     #[error("No memory")]
     NoMemory,
+    #[error("Message queue full")]
+    QueueFull,
+    #[error("Not found")]
+    NotFound,
 }
 
 impl DeadBeef {
@@ -85,7 +123,12 @@ impl DeadBeef {
         unsafe { &*self.ptr }
     }
 
-    pub fn sendmessage(msg: u32, ctx: usize, p1: u32, p2: u32) -> i32 {
+    /// Post a raw message, see `send` for the player's control messages.
+    ///
+    /// # Safety
+    /// Structured events (`msg >= DB_EV_FIRST`) need `ctx` to be an event from
+    /// `event_alloc`, which DeadBeef takes ownership of.
+    pub unsafe fn sendmessage(msg: u32, ctx: usize, p1: u32, p2: u32) -> i32 {
         let deadbeef = unsafe { DeadBeef::deadbeef() };
 
         let sendmessage = deadbeef.get().sendmessage.unwrap();
@@ -106,23 +149,6 @@ impl DeadBeef {
         }
     }
 
-    pub fn streamer_read(buf: *mut c_void, len: usize) -> i32 {
-        let deadbeef = unsafe { DeadBeef::deadbeef() };
-
-        let streamer_read = deadbeef.get().streamer_read.unwrap();
-
-        unsafe { streamer_read(buf as *mut i8 , len as i32) }
-    }
-
-    pub fn streamer_ok_to_read(len: i32) -> i32 {
-        let deadbeef = unsafe { DeadBeef::deadbeef() };
-
-        let streamer_ok_to_read = deadbeef.get().streamer_ok_to_read.unwrap();
-
-        unsafe { streamer_ok_to_read(len as i32) }
-
-    }
-
     pub fn conf_get_str(item: impl Into<String>, default: impl Into<String>) -> String {
         let deadbeef = unsafe { DeadBeef::deadbeef() };
 
@@ -137,6 +163,16 @@ impl DeadBeef {
         return cstr.expect("null terminated string").to_string_lossy().to_string();
     }
 
+    pub fn conf_set_str(item: impl Into<String>, value: impl Into<String>) {
+        let deadbeef = unsafe { DeadBeef::deadbeef() };
+
+        let item = LossyCString::new(item.into());
+        let value = LossyCString::new(value.into());
+        let conf_set_str = deadbeef.get().conf_set_str.unwrap();
+
+        unsafe { conf_set_str(item.as_ptr(), value.as_ptr()); }
+    }
+
     pub fn volume_set_amp(vol: f32) {
         let deadbeef = unsafe { DeadBeef::deadbeef() };
         let volume_set_amp = deadbeef.get().volume_set_amp.unwrap();
@@ -165,41 +201,18 @@ impl DeadBeef {
         Self::titleformat_for_item(format, &track)
     }
     
+    /// One-off evaluation, keep a `TitleFormat` around for repeated use.
     pub fn titleformat_for_item(format: impl Into<String>, item: &PlItem) -> Result<String, DB_TF_Error> {
-        let deadbeef = unsafe { DeadBeef::deadbeef() };
-
-        let format = LossyCString::new(format.into());
-
-        let tf_compile = deadbeef.get().tf_compile.unwrap();
-        let tf_eval = deadbeef.get().tf_eval.unwrap();
-        let tf_free = deadbeef.get().tf_free.unwrap();
-
-        let mut buf: Vec<u8> = vec![0; 4096];
-
-        let tf = unsafe { tf_compile(format.as_ptr()) };
-        if tf <= std::ptr::null_mut() {
-            return Err(DB_TF_Error::CompileError);
-        }
-        let mut ctx = ddb_tf_context_t {
-            _size: std::mem::size_of::<ddb_tf_context_t>() as i32,
-            flags: DDB_TF_CONTEXT_NO_DYNAMIC,
-            it: item.as_ptr(),
-            ..Default::default()
-        };
-        unsafe {
-            if tf_eval(&mut ctx as *mut _, tf, buf.as_mut_ptr() as *mut i8, 4096) <= 0 {
-                return Err(DB_TF_Error::EvalError);
-            }
-            tf_free(tf);
-        }
-        let cstr = std::ffi::CStr::from_bytes_until_nul(&buf);
-        Ok(cstr.expect("null terminated string").to_string_lossy().to_string())
+        TitleFormat::compile(format)?.eval(item)
     }
 
 
 }
 
 
+/// An owned reference to a track, released on drop.
+// Transparent so a `&PlItem` can be lent out straight from a `DB_playItem_t *`
+#[repr(transparent)]
 pub struct PlItem {
     ptr: ptr::NonNull<DB_playItem_s>,
 }
@@ -212,6 +225,19 @@ impl PlItem {
         })
     }
 
+    /// Borrow the track a pointer inside some DeadBeef struct refers to,
+    /// without taking a reference of our own.
+    ///
+    /// # Safety
+    /// The track must stay referenced by someone else for `'a`.
+    pub(crate) unsafe fn from_raw_ref<'a>(ptr: &'a *mut DB_playItem_s) -> Option<&'a PlItem> {
+        if ptr.is_null() {
+            None
+        } else {
+            Some(&*(ptr as *const *mut DB_playItem_s as *const PlItem))
+        }
+    }
+
     pub fn pl_item_unref(item: *mut DB_playItem_s) {
         let deadbeef = unsafe { DeadBeef::deadbeef() };
         let pl_item_unref = deadbeef.get().pl_item_unref.unwrap();
@@ -219,11 +245,17 @@ impl PlItem {
         unsafe { pl_item_unref(item); }
     }
 
-    fn as_ptr(&self) -> *mut DB_playItem_s {
+    pub(crate) fn as_ptr(&self) -> *mut DB_playItem_s {
         self.ptr.as_ptr()
     }
 }
 
+impl std::fmt::Debug for PlItem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("PlItem").field(&self.ptr).finish()
+    }
+}
+
 impl std::ops::Drop for PlItem {
     fn drop(&mut self) {
         PlItem::pl_item_unref(self.ptr.as_ptr());
@@ -261,16 +293,3 @@ impl std::fmt::Debug for PlaybackState {
         f.write_str(&name)
     }
 }
-
-impl PartialEq for ddb_waveformat_t {
-    fn eq(&self, other: &Self) -> bool {
-        self.channels == other.channels &&
-        self.samplerate == other.samplerate &&
-        self.bps == other.bps &&
-        self.channelmask == other.channelmask &&
-        self.is_float == other.is_float &&
-        self.flags == other.flags
-    }
-}
-
-impl Eq for ddb_waveformat_t {}
@@ -0,0 +1,214 @@
+use crate::*;
+
+use std::ffi::{c_char, CStr};
+use std::marker::PhantomData;
+
+/// Holds the playlist lock until dropped. Recursive, like the config lock.
+pub struct PlLock {
+    // The lock belongs to the thread that took it
+    _not_send: PhantomData<*const ()>,
+}
+
+impl Drop for PlLock {
+    fn drop(&mut self) {
+        let deadbeef = unsafe { DeadBeef::deadbeef() };
+        let pl_unlock = deadbeef.get().pl_unlock.unwrap();
+
+        unsafe { pl_unlock(); }
+    }
+}
+
+impl DeadBeef {
+    pub fn pl_lock() -> PlLock {
+        let deadbeef = unsafe { DeadBeef::deadbeef() };
+        let pl_lock = deadbeef.get().pl_lock.unwrap();
+
+        unsafe { pl_lock(); }
+        PlLock { _not_send: PhantomData }
+    }
+}
+
+/// Which replaygain value to look up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReplayGain {
+    AlbumGain,
+    AlbumPeak,
+    TrackGain,
+    TrackPeak,
+}
+
+impl ReplayGain {
+    fn as_raw(self) -> i32 {
+        (match self {
+            Self::AlbumGain => DDB_REPLAYGAIN_ALBUMGAIN,
+            Self::AlbumPeak => DDB_REPLAYGAIN_ALBUMPEAK,
+            Self::TrackGain => DDB_REPLAYGAIN_TRACKGAIN,
+            Self::TrackPeak => DDB_REPLAYGAIN_TRACKPEAK,
+        }) as i32
+    }
+}
+
+/// Copy out a string owned by DeadBeef, only valid under the playlist lock.
+unsafe fn copy_str(s: *const c_char) -> Option<String> {
+    if s.is_null() {
+        None
+    } else {
+        Some(CStr::from_ptr(s).to_string_lossy().to_string())
+    }
+}
+
+/// Metadata access. Everything is copied out under the playlist lock, so
+/// nothing returned here can be pulled out from under the caller.
+///
+/// Keys starting with `:` hold technical info, such as `:FILETYPE`, `:BITRATE`
+/// and `:CHANNELS`. Only the first of a tag's values is returned.
+impl PlItem {
+    /// A tag, or its override when one is set.
+    pub fn meta(&self, key: impl Into<String>) -> Option<String> {
+        let deadbeef = unsafe { DeadBeef::deadbeef() };
+
+        let key = LossyCString::new(key.into());
+        let pl_find_meta = deadbeef.get().pl_find_meta.unwrap();
+
+        let _lock = DeadBeef::pl_lock();
+        unsafe { copy_str(pl_find_meta(self.as_ptr(), key.as_ptr())) }
+    }
+
+    /// A tag as read from the file, ignoring any override.
+    pub fn meta_raw(&self, key: impl Into<String>) -> Option<String> {
+        let deadbeef = unsafe { DeadBeef::deadbeef() };
+
+        let key = LossyCString::new(key.into());
+        let pl_find_meta_raw = deadbeef.get().pl_find_meta_raw.unwrap();
+
+        let _lock = DeadBeef::pl_lock();
+        unsafe { copy_str(pl_find_meta_raw(self.as_ptr(), key.as_ptr())) }
+    }
+
+    pub fn meta_exists(&self, key: impl Into<String>) -> bool {
+        let deadbeef = unsafe { DeadBeef::deadbeef() };
+
+        let key = LossyCString::new(key.into());
+        let pl_meta_exists = deadbeef.get().pl_meta_exists.unwrap();
+
+        let _lock = DeadBeef::pl_lock();
+        unsafe { pl_meta_exists(self.as_ptr(), key.as_ptr()) != 0 }
+    }
+
+    pub fn meta_int(&self, key: impl Into<String>) -> Option<i32> {
+        let deadbeef = unsafe { DeadBeef::deadbeef() };
+
+        let key = key.into();
+        let pl_find_meta_int = deadbeef.get().pl_find_meta_int.unwrap();
+
+        // Keep the item from changing between the two calls
+        let _lock = DeadBeef::pl_lock();
+        if !self.meta_exists(key.as_str()) {
+            return None;
+        }
+        let key = LossyCString::new(key);
+        Some(unsafe { pl_find_meta_int(self.as_ptr(), key.as_ptr(), 0) })
+    }
+
+    pub fn meta_float(&self, key: impl Into<String>) -> Option<f32> {
+        let deadbeef = unsafe { DeadBeef::deadbeef() };
+
+        let key = key.into();
+        let pl_find_meta_float = deadbeef.get().pl_find_meta_float.unwrap();
+
+        let _lock = DeadBeef::pl_lock();
+        if !self.meta_exists(key.as_str()) {
+            return None;
+        }
+        let key = LossyCString::new(key);
+        Some(unsafe { pl_find_meta_float(self.as_ptr(), key.as_ptr(), 0.0) })
+    }
+
+    /// Every key and value, in the order DeadBeef keeps them.
+    pub fn metadata(&self) -> Vec<(String, String)> {
+        let deadbeef = unsafe { DeadBeef::deadbeef() };
+        let pl_get_metadata_head = deadbeef.get().pl_get_metadata_head.unwrap();
+
+        let _lock = DeadBeef::pl_lock();
+        let mut items = Vec::new();
+        let mut meta = unsafe { pl_get_metadata_head(self.as_ptr()) };
+        while let Some(m) = unsafe { meta.as_ref() } {
+            if let Some(key) = unsafe { copy_str(m.key) } {
+                let value = unsafe { copy_str(m.value) }.unwrap_or_default();
+                items.push((key, value));
+            }
+            meta = m.next;
+        }
+        items
+    }
+
+    /// Set a tag, replacing all of its current values.
+    pub fn replace_meta(&self, key: impl Into<String>, value: impl Into<String>) {
+        let deadbeef = unsafe { DeadBeef::deadbeef() };
+
+        let key = LossyCString::new(key.into());
+        let value = LossyCString::new(value.into());
+        let pl_replace_meta = deadbeef.get().pl_replace_meta.unwrap();
+
+        let _lock = DeadBeef::pl_lock();
+        unsafe { pl_replace_meta(self.as_ptr(), key.as_ptr(), value.as_ptr()); }
+    }
+
+    pub fn delete_meta(&self, key: impl Into<String>) {
+        let deadbeef = unsafe { DeadBeef::deadbeef() };
+
+        let key = LossyCString::new(key.into());
+        let pl_delete_meta = deadbeef.get().pl_delete_meta.unwrap();
+
+        let _lock = DeadBeef::pl_lock();
+        unsafe { pl_delete_meta(self.as_ptr(), key.as_ptr()); }
+    }
+
+    /// Length in seconds, `None` for streams and other tracks of unknown length.
+    pub fn duration(&self) -> Option<f32> {
+        let deadbeef = unsafe { DeadBeef::deadbeef() };
+        let pl_get_item_duration = deadbeef.get().pl_get_item_duration.unwrap();
+
+        let _lock = DeadBeef::pl_lock();
+        let duration = unsafe { pl_get_item_duration(self.as_ptr()) };
+        (duration >= 0.0).then_some(duration)
+    }
+
+    /// Gain in dB or peak as a linear factor, 0 when not set.
+    pub fn replaygain(&self, which: ReplayGain) -> f32 {
+        let deadbeef = unsafe { DeadBeef::deadbeef() };
+        let pl_get_item_replaygain = deadbeef.get().pl_get_item_replaygain.unwrap();
+
+        let _lock = DeadBeef::pl_lock();
+        unsafe { pl_get_item_replaygain(self.as_ptr(), which.as_raw()) }
+    }
+
+    /// Codec name, such as "FLAC" or "MP3".
+    pub fn filetype(&self) -> Option<String> {
+        self.meta(":FILETYPE")
+    }
+
+    /// Bitrate in kbps.
+    pub fn bitrate(&self) -> Option<i32> {
+        self.meta_int(":BITRATE")
+    }
+
+    pub fn channels(&self) -> Option<i32> {
+        self.meta_int(":CHANNELS")
+    }
+
+    pub fn samplerate(&self) -> Option<i32> {
+        self.meta_int(":SAMPLERATE")
+    }
+}
+
+/// Takes another reference to the same track.
+impl Clone for PlItem {
+    fn clone(&self) -> Self {
+        let deadbeef = unsafe { DeadBeef::deadbeef() };
+        let pl_item_ref = deadbeef.get().pl_item_ref.unwrap();
+
+        unsafe { pl_item_ref(self.as_ptr()); }
+        Self { ptr: self.ptr }
+    }
+}
@@ -0,0 +1,228 @@
+use crate::*;
+
+use std::ffi::CStr;
+use std::marker::PhantomData;
+
+/// Buffer size titles are read into at first, grown as needed up to `MAX_TITLE_LEN`.
+const INITIAL_TITLE_LEN: usize = 4096;
+const MAX_TITLE_LEN: usize = 1 << 20;
+
+/// An owned reference to a playlist, released on drop.
+///
+/// Methods that change the playlist or its selection take a `PlLock`, so a
+/// series of changes can be made without other threads seeing them half done.
+pub struct Playlist {
+    ptr: ptr::NonNull<ddb_playlist_t>,
+}
+
+impl Playlist {
+    /// Takes over a reference the caller holds, as returned by the `plt_get_*` functions.
+    pub fn from_raw(ptr: *mut ddb_playlist_t) -> Option<Self> {
+        ptr::NonNull::new(ptr).map(|ptr| Self { ptr })
+    }
+
+    pub(crate) fn as_ptr(&self) -> *mut ddb_playlist_t {
+        self.ptr.as_ptr()
+    }
+
+    /// The playlist shown in the UI and played from.
+    pub fn current() -> Option<Self> {
+        let deadbeef = unsafe { DeadBeef::deadbeef() };
+        let plt_get_curr = deadbeef.get().plt_get_curr.unwrap();
+
+        Self::from_raw(unsafe { plt_get_curr() })
+    }
+
+    pub fn for_index(idx: i32) -> Option<Self> {
+        let deadbeef = unsafe { DeadBeef::deadbeef() };
+        let plt_get_for_idx = deadbeef.get().plt_get_for_idx.unwrap();
+
+        Self::from_raw(unsafe { plt_get_for_idx(idx) })
+    }
+
+    pub fn find_by_name(name: impl Into<String>) -> Option<Self> {
+        let deadbeef = unsafe { DeadBeef::deadbeef() };
+
+        let name = LossyCString::new(name.into());
+        let plt_find_by_name = deadbeef.get().plt_find_by_name.unwrap();
+
+        Self::from_raw(unsafe { plt_find_by_name(name.as_ptr()) })
+    }
+
+    /// Number of playlists.
+    pub fn count() -> i32 {
+        let deadbeef = unsafe { DeadBeef::deadbeef() };
+        let plt_get_count = deadbeef.get().plt_get_count.unwrap();
+
+        unsafe { plt_get_count() }
+    }
+
+    pub fn set_current(&self, _lock: &PlLock) {
+        let deadbeef = unsafe { DeadBeef::deadbeef() };
+        let plt_set_curr = deadbeef.get().plt_set_curr.unwrap();
+
+        unsafe { plt_set_curr(self.as_ptr()); }
+    }
+
+    /// Position among the playlist tabs, `None` if it has been removed.
+    pub fn index(&self) -> Option<i32> {
+        let deadbeef = unsafe { DeadBeef::deadbeef() };
+        let plt_get_idx = deadbeef.get().plt_get_idx.unwrap();
+
+        let idx = unsafe { plt_get_idx(self.as_ptr()) };
+        (idx >= 0).then_some(idx)
+    }
+
+    pub fn title(&self) -> String {
+        let deadbeef = unsafe { DeadBeef::deadbeef() };
+        let plt_get_title = deadbeef.get().plt_get_title.unwrap();
+
+        let mut buf: Vec<u8> = vec![0; INITIAL_TITLE_LEN];
+        loop {
+            unsafe { plt_get_title(self.as_ptr(), buf.as_mut_ptr() as *mut i8, buf.len() as i32); }
+
+            let Ok(cstr) = CStr::from_bytes_until_nul(&buf) else {
+                return String::new();
+            };
+            // The title is cut to fit, so a full buffer may not be all of it
+            if cstr.to_bytes().len() + 1 >= buf.len() && buf.len() < MAX_TITLE_LEN {
+                buf = vec![0; buf.len() * 2];
+                continue;
+            }
+            return cstr.to_string_lossy().to_string();
+        }
+    }
+
+    pub fn set_title(&self, title: impl Into<String>, _lock: &PlLock) {
+        let deadbeef = unsafe { DeadBeef::deadbeef() };
+
+        let title = LossyCString::new(title.into());
+        let plt_set_title = deadbeef.get().plt_set_title.unwrap();
+
+        unsafe { plt_set_title(self.as_ptr(), title.as_ptr()); }
+    }
+
+    /// Number of tracks in `iter`, `PL_MAIN` or `PL_SEARCH`.
+    pub fn item_count(&self, iter: i32) -> i32 {
+        let deadbeef = unsafe { DeadBeef::deadbeef() };
+        let plt_get_item_count = deadbeef.get().plt_get_item_count.unwrap();
+
+        unsafe { plt_get_item_count(self.as_ptr(), iter) }
+    }
+
+    pub fn selected_count(&self) -> i32 {
+        let deadbeef = unsafe { DeadBeef::deadbeef() };
+        let plt_getselcount = deadbeef.get().plt_getselcount.unwrap();
+
+        unsafe { plt_getselcount(self.as_ptr()) }
+    }
+
+    /// Walk the tracks in `iter`. Borrowing the lock keeps the list from
+    /// changing underneath, the tracks themselves are owned references.
+    pub fn items<'a>(&'a self, iter: i32, _lock: &'a PlLock) -> PlaylistItems<'a> {
+        let deadbeef = unsafe { DeadBeef::deadbeef() };
+        let plt_get_first = deadbeef.get().plt_get_first.unwrap();
+
+        let first = PlItem::from_raw(unsafe { plt_get_first(self.as_ptr(), iter) }).ok();
+        PlaylistItems {
+            next: first,
+            iter,
+            _lock: PhantomData,
+        }
+    }
+
+    pub fn item_at(&self, idx: i32, iter: i32) -> Option<PlItem> {
+        let deadbeef = unsafe { DeadBeef::deadbeef() };
+        let plt_get_item_for_idx = deadbeef.get().plt_get_item_for_idx.unwrap();
+
+        PlItem::from_raw(unsafe { plt_get_item_for_idx(self.as_ptr(), idx, iter) }).ok()
+    }
+
+    pub fn item_index(&self, item: &PlItem, iter: i32) -> Option<i32> {
+        let deadbeef = unsafe { DeadBeef::deadbeef() };
+        let plt_get_item_idx = deadbeef.get().plt_get_item_idx.unwrap();
+
+        let idx = unsafe { plt_get_item_idx(self.as_ptr(), item.as_ptr(), iter) };
+        (idx >= 0).then_some(idx)
+    }
+
+    /// Insert `item` after `after`, or at the top. The playlist takes its own reference.
+    pub fn insert_item(&self, after: Option<&PlItem>, item: &PlItem, _lock: &PlLock) {
+        let deadbeef = unsafe { DeadBeef::deadbeef() };
+        let plt_insert_item = deadbeef.get().plt_insert_item.unwrap();
+
+        let after = after.map_or(ptr::null_mut(), |it| it.as_ptr());
+        unsafe { plt_insert_item(self.as_ptr(), after, item.as_ptr()); }
+    }
+
+    pub fn remove_item(&self, item: &PlItem, _lock: &PlLock) -> bool {
+        let deadbeef = unsafe { DeadBeef::deadbeef() };
+        let plt_remove_item = deadbeef.get().plt_remove_item.unwrap();
+
+        unsafe { plt_remove_item(self.as_ptr(), item.as_ptr()) == 0 }
+    }
+
+    /// Whether `item` is in this playlist and selected there.
+    pub fn is_selected(&self, item: &PlItem, _lock: &PlLock) -> bool {
+        let deadbeef = unsafe { DeadBeef::deadbeef() };
+        let pl_is_selected = deadbeef.get().pl_is_selected.unwrap();
+
+        // The flag lives on the item, which says nothing about which playlist it is in
+        self.item_index(item, PL_MAIN).is_some() && unsafe { pl_is_selected(item.as_ptr()) != 0 }
+    }
+
+    /// Select or deselect `item`, keeping the playlist's selection count right.
+    pub fn set_selected(&self, item: &PlItem, selected: bool, _lock: &PlLock) {
+        let deadbeef = unsafe { DeadBeef::deadbeef() };
+        let plt_item_set_selected = deadbeef.get().plt_item_set_selected.unwrap();
+
+        unsafe { plt_item_set_selected(self.as_ptr(), item.as_ptr(), selected as i32); }
+    }
+
+    /// Mark the playlist as needing to be saved, after changing it.
+    pub fn modified(&self, _lock: &PlLock) {
+        let deadbeef = unsafe { DeadBeef::deadbeef() };
+        let plt_modified = deadbeef.get().plt_modified.unwrap();
+
+        unsafe { plt_modified(self.as_ptr()); }
+    }
+}
+
+impl Clone for Playlist {
+    fn clone(&self) -> Self {
+        let deadbeef = unsafe { DeadBeef::deadbeef() };
+        let plt_ref = deadbeef.get().plt_ref.unwrap();
+
+        unsafe { plt_ref(self.as_ptr()); }
+        Self { ptr: self.ptr }
+    }
+}
+
+impl Drop for Playlist {
+    fn drop(&mut self) {
+        let deadbeef = unsafe { DeadBeef::deadbeef() };
+        let plt_unref = deadbeef.get().plt_unref.unwrap();
+
+        unsafe { plt_unref(self.as_ptr()); }
+    }
+}
+
+/// Iterator returned by `Playlist::items`.
+pub struct PlaylistItems<'a> {
+    next: Option<PlItem>,
+    iter: i32,
+    _lock: PhantomData<&'a PlLock>,
+}
+
+impl Iterator for PlaylistItems<'_> {
+    type Item = PlItem;
+
+    fn next(&mut self) -> Option<PlItem> {
+        let deadbeef = unsafe { DeadBeef::deadbeef() };
+        let pl_get_next = deadbeef.get().pl_get_next.unwrap();
+
+        let item = self.next.take()?;
+        self.next = PlItem::from_raw(unsafe { pl_get_next(item.as_ptr(), self.iter) }).ok();
+        Some(item)
+    }
+}
@@ -0,0 +1,140 @@
+use crate::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Shuffle {
+    Off,
+    Tracks,
+    Random,
+    Albums,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Repeat {
+    All,
+    Off,
+    Single,
+}
+
+impl DeadBeef {
+    /// Fill `buf` with decoded audio in the output format, returns the number of
+    /// bytes read, 0 or less when there was nothing.
+    pub fn streamer_read(buf: &mut [u8]) -> i32 {
+        let deadbeef = unsafe { DeadBeef::deadbeef() };
+
+        let streamer_read = deadbeef.get().streamer_read.unwrap();
+        let len = buf.len().min(i32::MAX as usize) as i32;
+
+        unsafe { streamer_read(buf.as_mut_ptr() as *mut i8, len) }
+    }
+
+    /// Like `streamer_read`, but only asks for whole frames of `frame_size` bytes.
+    /// Returns the number of frames read.
+    pub fn streamer_read_frames(buf: &mut [u8], frame_size: usize) -> usize {
+        let len = buf.len() / frame_size.max(1) * frame_size;
+        let bytesread = Self::streamer_read(&mut buf[..len]);
+        bytesread.max(0) as usize / frame_size.max(1)
+    }
+
+    /// Whether the streamer can fill all of `buf` right now.
+    pub fn streamer_ok_to_read(buf: &[u8]) -> bool {
+        let deadbeef = unsafe { DeadBeef::deadbeef() };
+
+        let streamer_ok_to_read = deadbeef.get().streamer_ok_to_read.unwrap();
+        let len = buf.len().min(i32::MAX as usize) as i32;
+
+        unsafe { streamer_ok_to_read(len) > 0 }
+    }
+
+    /// Whether the streamer has anything at all to read.
+    pub fn streamer_has_data() -> bool {
+        let deadbeef = unsafe { DeadBeef::deadbeef() };
+
+        let streamer_ok_to_read = deadbeef.get().streamer_ok_to_read.unwrap();
+
+        unsafe { streamer_ok_to_read(-1) > 0 }
+    }
+
+    /// Playback position in the current track, in seconds.
+    pub fn streamer_get_playpos() -> f32 {
+        let deadbeef = unsafe { DeadBeef::deadbeef() };
+        let streamer_get_playpos = deadbeef.get().streamer_get_playpos.unwrap();
+
+        unsafe { streamer_get_playpos() }
+    }
+
+    /// The track being decoded, which runs ahead of the one being heard.
+    pub fn streamer_get_streaming_track() -> Option<PlItem> {
+        let deadbeef = unsafe { DeadBeef::deadbeef() };
+        let streamer_get_streaming_track = deadbeef.get().streamer_get_streaming_track.unwrap();
+
+        PlItem::from_raw(unsafe { streamer_get_streaming_track() }).ok()
+    }
+
+    /// The format the current decoder produces, before DSP and conversion
+    /// to the output format.
+    pub fn streamer_get_current_fileinfo() -> Result<WaveFormat, DB_Format_Error> {
+        let deadbeef = unsafe { DeadBeef::deadbeef() };
+        let streamer_get_current_fileinfo = deadbeef.get().streamer_get_current_fileinfo.unwrap();
+
+        // Copied straight away, the streamer may close the file at any time
+        let fileinfo = unsafe { streamer_get_current_fileinfo() };
+        let fmt = unsafe { fileinfo.as_ref() }.ok_or(DB_Format_Error::NoFile)?.fmt;
+        WaveFormat::try_from(fmt)
+    }
+
+    /// Drop buffered audio. A full reset also closes the current track.
+    pub fn streamer_reset(full: bool) {
+        let deadbeef = unsafe { DeadBeef::deadbeef() };
+        let streamer_reset = deadbeef.get().streamer_reset.unwrap();
+
+        unsafe { streamer_reset(full as i32); }
+    }
+
+    pub fn streamer_get_shuffle() -> Shuffle {
+        let deadbeef = unsafe { DeadBeef::deadbeef() };
+        let streamer_get_shuffle = deadbeef.get().streamer_get_shuffle.unwrap();
+
+        match unsafe { streamer_get_shuffle() } {
+            DDB_SHUFFLE_TRACKS => Shuffle::Tracks,
+            DDB_SHUFFLE_RANDOM => Shuffle::Random,
+            DDB_SHUFFLE_ALBUMS => Shuffle::Albums,
+            _ => Shuffle::Off,
+        }
+    }
+
+    pub fn streamer_set_shuffle(shuffle: Shuffle) {
+        let deadbeef = unsafe { DeadBeef::deadbeef() };
+        let streamer_set_shuffle = deadbeef.get().streamer_set_shuffle.unwrap();
+
+        let raw = match shuffle {
+            Shuffle::Off => DDB_SHUFFLE_OFF,
+            Shuffle::Tracks => DDB_SHUFFLE_TRACKS,
+            Shuffle::Random => DDB_SHUFFLE_RANDOM,
+            Shuffle::Albums => DDB_SHUFFLE_ALBUMS,
+        };
+        unsafe { streamer_set_shuffle(raw); }
+    }
+
+    pub fn streamer_get_repeat() -> Repeat {
+        let deadbeef = unsafe { DeadBeef::deadbeef() };
+        let streamer_get_repeat = deadbeef.get().streamer_get_repeat.unwrap();
+
+        match unsafe { streamer_get_repeat() } {
+            DDB_REPEAT_OFF => Repeat::Off,
+            DDB_REPEAT_SINGLE => Repeat::Single,
+            _ => Repeat::All,
+        }
+    }
+
+    pub fn streamer_set_repeat(repeat: Repeat) {
+        let deadbeef = unsafe { DeadBeef::deadbeef() };
+        let streamer_set_repeat = deadbeef.get().streamer_set_repeat.unwrap();
+
+        let raw = match repeat {
+            Repeat::All => DDB_REPEAT_ALL,
+            Repeat::Off => DDB_REPEAT_OFF,
+            Repeat::Single => DDB_REPEAT_SINGLE,
+        };
+        unsafe { streamer_set_repeat(raw); }
+    }
+}
@@ -0,0 +1,150 @@
+use crate::*;
+
+use std::ffi::c_char;
+
+/// Output buffer size to start with, grown as needed up to `MAX_OUTPUT`.
+const INITIAL_OUTPUT: usize = 4096;
+const MAX_OUTPUT: usize = 1 << 20;
+
+/// A compiled title formatting script, freed on drop.
+///
+/// Compile once and keep it around, rather than paying for `tf_compile` on
+/// every evaluation.
+pub struct TitleFormat {
+    code: ptr::NonNull<c_char>,
+}
+
+// The bytecode is never modified after compiling
+unsafe impl Send for TitleFormat {}
+unsafe impl Sync for TitleFormat {}
+
+impl TitleFormat {
+    pub fn compile(script: impl Into<String>) -> Result<Self, DB_TF_Error> {
+        let deadbeef = unsafe { DeadBeef::deadbeef() };
+
+        let script = LossyCString::new(script.into());
+        let tf_compile = deadbeef.get().tf_compile.unwrap();
+
+        let code = unsafe { tf_compile(script.as_ptr()) };
+        let code = ptr::NonNull::new(code).ok_or(DB_TF_Error::CompileError)?;
+        Ok(Self { code })
+    }
+
+    /// Evaluate for `item`, leaving out dynamic fields such as `%playback_time%`.
+    pub fn eval(&self, item: &PlItem) -> Result<String, DB_TF_Error> {
+        self.eval_in(&TfContext::new().item(item).flags(DDB_TF_CONTEXT_NO_DYNAMIC))
+    }
+
+    pub fn eval_in(&self, context: &TfContext) -> Result<String, DB_TF_Error> {
+        let deadbeef = unsafe { DeadBeef::deadbeef() };
+        let tf_eval = deadbeef.get().tf_eval.unwrap();
+
+        let mut buf: Vec<u8> = vec![0; INITIAL_OUTPUT];
+        loop {
+            let mut ctx = context.to_raw();
+            let len = unsafe {
+                tf_eval(&mut ctx as *mut _, self.code.as_ptr(), buf.as_mut_ptr() as *mut c_char, buf.len() as i32)
+            };
+            if len < 0 {
+                return Err(DB_TF_Error::EvalError);
+            }
+            let len = len as usize;
+            // A full buffer may mean the output got cut short
+            if len + 1 >= buf.len() && buf.len() < MAX_OUTPUT {
+                buf = vec![0; buf.len() * 2];
+                continue;
+            }
+            return Ok(String::from_utf8_lossy(&buf[..len.min(buf.len())]).to_string());
+        }
+    }
+}
+
+impl Drop for TitleFormat {
+    fn drop(&mut self) {
+        let deadbeef = unsafe { DeadBeef::deadbeef() };
+        let tf_free = deadbeef.get().tf_free.unwrap();
+
+        unsafe { tf_free(self.code.as_ptr()); }
+    }
+}
+
+/// What a `TitleFormat` is evaluated against.
+#[derive(Clone, Copy)]
+pub struct TfContext<'a> {
+    item: Option<&'a PlItem>,
+    playlist: *mut ddb_playlist_t,
+    index: Option<i32>,
+    iter: i32,
+    flags: u32,
+}
+
+impl<'a> TfContext<'a> {
+    pub fn new() -> Self {
+        Self {
+            item: None,
+            playlist: ptr::null_mut(),
+            index: None,
+            iter: PL_MAIN,
+            flags: 0,
+        }
+    }
+
+    pub fn item(mut self, item: &'a PlItem) -> Self {
+        self.item = Some(item);
+        self
+    }
+
+    /// The playlist `item` is in, needed for playlist related fields.
+    pub fn playlist(self, playlist: &'a Playlist) -> Self {
+        unsafe { self.playlist_ptr(playlist.as_ptr()) }
+    }
+
+    /// Like `playlist`, for a playlist pointer from elsewhere.
+    ///
+    /// # Safety
+    /// `playlist` must be null or a playlist that outlives the evaluation.
+    pub unsafe fn playlist_ptr(mut self, playlist: *mut ddb_playlist_t) -> Self {
+        self.playlist = playlist;
+        self
+    }
+
+    /// Position of the item in its playlist, for `%list_index%`.
+    pub fn index(mut self, index: i32) -> Self {
+        self.index = Some(index);
+        self
+    }
+
+    /// Which list of the playlist to look at, `PL_MAIN` or `PL_SEARCH`.
+    pub fn iter(mut self, iter: i32) -> Self {
+        self.iter = iter;
+        self
+    }
+
+    /// `DDB_TF_CONTEXT_*` flags, `DDB_TF_CONTEXT_HAS_INDEX` is set by `index`.
+    pub fn flags(mut self, flags: u32) -> Self {
+        self.flags = flags;
+        self
+    }
+
+    fn to_raw(&self) -> ddb_tf_context_t {
+        let mut flags = self.flags;
+        if self.index.is_some() {
+            flags |= DDB_TF_CONTEXT_HAS_INDEX;
+        }
+        ddb_tf_context_t {
+            _size: std::mem::size_of::<ddb_tf_context_t>() as i32,
+            flags,
+            it: self.item.map_or(ptr::null_mut(), |item| item.as_ptr()),
+            plt: self.playlist,
+            idx: self.index.unwrap_or(0),
+            iter: self.iter,
+            ..Default::default()
+        }
+    }
+}
+
+impl Default for TfContext<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
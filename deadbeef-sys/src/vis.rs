@@ -0,0 +1,107 @@
+use crate::*;
+
+use std::ffi::c_void;
+
+type VisCallback = Box<dyn FnMut(&AudioData) + Send>;
+
+/// A block of visualization data, only valid for the duration of the callback.
+pub struct AudioData<'a> {
+    raw: &'a ddb_audio_data_t,
+}
+
+impl<'a> AudioData<'a> {
+    /// Format of the audio the data was taken from. Samples are always `f32`.
+    pub fn format(&self) -> Option<WaveFormat> {
+        let fmt = unsafe { self.raw.fmt.as_ref() }?;
+        WaveFormat::try_from(*fmt).ok()
+    }
+
+    pub fn channels(&self) -> usize {
+        unsafe { self.raw.fmt.as_ref() }.map_or(0, |fmt| fmt.channels.max(0) as usize)
+    }
+
+    /// Frames of audio, or frequency bins per channel for spectrum data.
+    pub fn frames(&self) -> usize {
+        self.raw.nframes.max(0) as usize
+    }
+
+    /// All of the data. Waveform samples are interleaved, spectrum data is
+    /// laid out one channel after the other.
+    pub fn data(&self) -> &'a [f32] {
+        let len = self.frames() * self.channels();
+        if self.raw.data.is_null() || len == 0 {
+            return &[];
+        }
+        unsafe { std::slice::from_raw_parts(self.raw.data, len) }
+    }
+
+    /// The bins for one channel of spectrum data.
+    pub fn spectrum(&self, channel: usize) -> Option<&'a [f32]> {
+        let frames = self.frames();
+        self.data().get(channel * frames..(channel + 1) * frames)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum VisKind {
+    Waveform,
+    Spectrum,
+}
+
+/// A registered visualization callback, unregistered on drop.
+///
+/// The callback runs on one of DeadBeef's background threads. Dropping the
+/// listener waits for a callback in progress, so don't drop it from inside one.
+pub struct VisListener {
+    callback: *mut VisCallback,
+    kind: VisKind,
+}
+
+// Unregistering works from any thread, and the callback itself is Send
+unsafe impl Send for VisListener {}
+
+impl Drop for VisListener {
+    fn drop(&mut self) {
+        let deadbeef = unsafe { DeadBeef::deadbeef() };
+
+        let unlisten = match self.kind {
+            VisKind::Waveform => deadbeef.get().vis_waveform_unlisten.unwrap(),
+            VisKind::Spectrum => deadbeef.get().vis_spectrum_unlisten.unwrap(),
+        };
+        unsafe {
+            unlisten(self.callback as *mut c_void);
+            drop(Box::from_raw(self.callback));
+        }
+    }
+}
+
+unsafe extern "C" fn vis_trampoline(ctx: *mut c_void, data: *const ddb_audio_data_t) {
+    let callback = &mut *(ctx as *mut VisCallback);
+    if let Some(raw) = data.as_ref() {
+        callback(&AudioData { raw });
+    }
+}
+
+impl DeadBeef {
+    /// Get the audio being played as interleaved `f32` samples, in blocks of any size.
+    pub fn vis_waveform_listen(callback: impl FnMut(&AudioData) + Send + 'static) -> VisListener {
+        let deadbeef = unsafe { DeadBeef::deadbeef() };
+        let vis_waveform_listen = deadbeef.get().vis_waveform_listen.unwrap();
+
+        let callback: VisCallback = Box::new(callback);
+        let callback = Box::into_raw(Box::new(callback));
+        unsafe { vis_waveform_listen(callback as *mut c_void, Some(vis_trampoline)); }
+        VisListener { callback, kind: VisKind::Waveform }
+    }
+
+    /// Get the spectrum of the audio being played, up to `DDB_FREQ_MAX_CHANNELS` channels.
+    pub fn vis_spectrum_listen(callback: impl FnMut(&AudioData) + Send + 'static) -> VisListener {
+        let deadbeef = unsafe { DeadBeef::deadbeef() };
+        let vis_spectrum_listen2 = deadbeef.get().vis_spectrum_listen2.unwrap();
+
+        let callback: VisCallback = Box::new(callback);
+        let callback = Box::into_raw(Box::new(callback));
+        unsafe { vis_spectrum_listen2(callback as *mut c_void, Some(vis_trampoline)); }
+        VisListener { callback, kind: VisKind::Spectrum }
+    }
+}
@@ -0,0 +1,396 @@
+use crate::*;
+
+use std::time::Duration;
+
+/// Highest channel count a channel mask can describe.
+pub const MAX_CHANNELS: u32 = 32;
+
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DB_Format_Error {
+    #[error("Invalid sample rate {0}")]
+    InvalidSampleRate(i32),
+    #[error("Invalid channel count {0}")]
+    InvalidChannels(i32),
+    #[error("Unsupported sample size {0} bits")]
+    InvalidBps(i32),
+    #[error("Float samples must be 32 bits, not {0}")]
+    InvalidFloatBps(i32),
+    #[error("No file is being decoded")]
+    NoFile,
+}
+
+/// A validated `ddb_waveformat_t`: a sample rate, a supported integer or
+/// float sample size and at least one channel.
+///
+/// Samples are interleaved and little endian, so one frame holds one sample
+/// for every channel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct WaveFormat {
+    samplerate: u32,
+    bps: u32,
+    channels: u32,
+    channelmask: u32,
+    is_float: bool,
+    flags: u32,
+}
+
+impl WaveFormat {
+    /// A format with the standard channel layout for `channels`.
+    pub fn new(samplerate: u32, bps: u32, channels: u32, is_float: bool) -> Result<Self, DB_Format_Error> {
+        if samplerate == 0 || samplerate > i32::MAX as u32 {
+            return Err(DB_Format_Error::InvalidSampleRate(samplerate as i32));
+        }
+        if channels == 0 || channels > MAX_CHANNELS {
+            return Err(DB_Format_Error::InvalidChannels(channels as i32));
+        }
+        if !matches!(bps, 8 | 16 | 24 | 32) {
+            return Err(DB_Format_Error::InvalidBps(bps as i32));
+        }
+        if is_float && bps != 32 {
+            return Err(DB_Format_Error::InvalidFloatBps(bps as i32));
+        }
+        Ok(Self {
+            samplerate,
+            bps,
+            channels,
+            channelmask: default_channelmask(channels),
+            is_float,
+            flags: 0,
+        })
+    }
+
+    /// Replace the channel layout. DeadBeef doesn't insist on the mask matching
+    /// the channel count, so neither does this.
+    pub fn with_channelmask(mut self, channelmask: u32) -> Self {
+        self.channelmask = channelmask;
+        self
+    }
+
+    /// `DDB_WAVEFORMAT_FLAG_*` bits.
+    pub fn with_flags(mut self, flags: u32) -> Self {
+        self.flags = flags;
+        self
+    }
+
+    pub fn samplerate(&self) -> u32 {
+        self.samplerate
+    }
+
+    pub fn bps(&self) -> u32 {
+        self.bps
+    }
+
+    pub fn channels(&self) -> u32 {
+        self.channels
+    }
+
+    pub fn channelmask(&self) -> u32 {
+        self.channelmask
+    }
+
+    pub fn flags(&self) -> u32 {
+        self.flags
+    }
+
+    pub fn is_float(&self) -> bool {
+        self.is_float
+    }
+
+    /// DSD packed into PCM frames, which must be passed on bit exact.
+    pub fn is_dop(&self) -> bool {
+        self.flags & DDB_WAVEFORMAT_FLAG_IS_DOP as u32 != 0
+    }
+
+    /// The speakers in the channel mask, in the order their samples appear in a frame.
+    pub fn speakers(&self) -> Speakers {
+        Speakers { mask: self.channelmask }
+    }
+
+    pub fn bytes_per_sample(&self) -> usize {
+        self.bps as usize / 8
+    }
+
+    pub fn bytes_per_frame(&self) -> usize {
+        self.bytes_per_sample() * self.channels as usize
+    }
+
+    pub fn frames_to_bytes(&self, frames: usize) -> usize {
+        frames * self.bytes_per_frame()
+    }
+
+    /// Whole frames in `bytes`, a partial frame at the end is not counted.
+    pub fn bytes_to_frames(&self, bytes: usize) -> usize {
+        bytes / self.bytes_per_frame()
+    }
+
+    /// `bytes` cut down to a whole number of frames.
+    pub fn align_bytes(&self, bytes: usize) -> usize {
+        bytes - bytes % self.bytes_per_frame()
+    }
+
+    pub fn frames_to_duration(&self, frames: usize) -> Duration {
+        let nanos = frames as u128 * 1_000_000_000 / self.samplerate as u128;
+        Duration::from_nanos(nanos as u64)
+    }
+
+    /// Frames played in `duration`, rounded down.
+    pub fn duration_to_frames(&self, duration: Duration) -> usize {
+        (duration.as_nanos() * self.samplerate as u128 / 1_000_000_000) as usize
+    }
+
+    pub fn bytes_to_duration(&self, bytes: usize) -> Duration {
+        self.frames_to_duration(self.bytes_to_frames(bytes))
+    }
+
+    pub fn duration_to_bytes(&self, duration: Duration) -> usize {
+        self.frames_to_bytes(self.duration_to_frames(duration))
+    }
+}
+
+/// 16 bit stereo at 44.1 kHz, what DeadBeef itself falls back to.
+impl Default for WaveFormat {
+    fn default() -> Self {
+        Self {
+            samplerate: 44100,
+            bps: 16,
+            channels: 2,
+            channelmask: default_channelmask(2),
+            is_float: false,
+            flags: 0,
+        }
+    }
+}
+
+impl TryFrom<ddb_waveformat_t> for WaveFormat {
+    type Error = DB_Format_Error;
+
+    fn try_from(raw: ddb_waveformat_t) -> Result<Self, Self::Error> {
+        if raw.samplerate <= 0 {
+            return Err(DB_Format_Error::InvalidSampleRate(raw.samplerate));
+        }
+        if raw.channels <= 0 {
+            return Err(DB_Format_Error::InvalidChannels(raw.channels));
+        }
+        if raw.bps <= 0 {
+            return Err(DB_Format_Error::InvalidBps(raw.bps));
+        }
+        Ok(Self::new(raw.samplerate as u32, raw.bps as u32, raw.channels as u32, raw.is_float != 0)?
+            .with_channelmask(raw.channelmask)
+            .with_flags(raw.flags))
+    }
+}
+
+impl From<WaveFormat> for ddb_waveformat_t {
+    fn from(fmt: WaveFormat) -> Self {
+        ddb_waveformat_t {
+            bps: fmt.bps as i32,
+            channels: fmt.channels as i32,
+            samplerate: fmt.samplerate as i32,
+            channelmask: fmt.channelmask,
+            is_float: fmt.is_float as i32,
+            flags: fmt.flags,
+        }
+    }
+}
+
+impl std::fmt::Display for WaveFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} bps{}{}, {} channels, {} Hz",
+            self.bps,
+            if self.is_float { " float" } else { "" },
+            if self.is_dop() { " DoP" } else { "" },
+            self.channels,
+            self.samplerate
+        )
+    }
+}
+
+/// The lowest `channels` bits, which is how DeadBeef lays out channels
+/// when nothing says otherwise.
+fn default_channelmask(channels: u32) -> u32 {
+    if channels >= 32 {
+        u32::MAX
+    } else {
+        (1 << channels) - 1
+    }
+}
+
+/// A speaker position from the `DDB_SPEAKER_*` channel mask bits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Speaker {
+    FrontLeft,
+    FrontRight,
+    FrontCenter,
+    LowFrequency,
+    BackLeft,
+    BackRight,
+    FrontLeftOfCenter,
+    FrontRightOfCenter,
+    BackCenter,
+    SideLeft,
+    SideRight,
+    TopCenter,
+    TopFrontLeft,
+    TopFrontCenter,
+    TopFrontRight,
+    TopBackLeft,
+    TopBackCenter,
+    TopBackRight,
+}
+
+impl Speaker {
+    /// Every speaker, in channel mask bit order.
+    pub const ALL: [Speaker; 18] = [
+        Self::FrontLeft,
+        Self::FrontRight,
+        Self::FrontCenter,
+        Self::LowFrequency,
+        Self::BackLeft,
+        Self::BackRight,
+        Self::FrontLeftOfCenter,
+        Self::FrontRightOfCenter,
+        Self::BackCenter,
+        Self::SideLeft,
+        Self::SideRight,
+        Self::TopCenter,
+        Self::TopFrontLeft,
+        Self::TopFrontCenter,
+        Self::TopFrontRight,
+        Self::TopBackLeft,
+        Self::TopBackCenter,
+        Self::TopBackRight,
+    ];
+
+    pub fn mask(self) -> u32 {
+        (match self {
+            Self::FrontLeft => DDB_SPEAKER_FRONT_LEFT,
+            Self::FrontRight => DDB_SPEAKER_FRONT_RIGHT,
+            Self::FrontCenter => DDB_SPEAKER_FRONT_CENTER,
+            Self::LowFrequency => DDB_SPEAKER_LOW_FREQUENCY,
+            Self::BackLeft => DDB_SPEAKER_BACK_LEFT,
+            Self::BackRight => DDB_SPEAKER_BACK_RIGHT,
+            Self::FrontLeftOfCenter => DDB_SPEAKER_FRONT_LEFT_OF_CENTER,
+            Self::FrontRightOfCenter => DDB_SPEAKER_FRONT_RIGHT_OF_CENTER,
+            Self::BackCenter => DDB_SPEAKER_BACK_CENTER,
+            Self::SideLeft => DDB_SPEAKER_SIDE_LEFT,
+            Self::SideRight => DDB_SPEAKER_SIDE_RIGHT,
+            Self::TopCenter => DDB_SPEAKER_TOP_CENTER,
+            Self::TopFrontLeft => DDB_SPEAKER_TOP_FRONT_LEFT,
+            Self::TopFrontCenter => DDB_SPEAKER_TOP_FRONT_CENTER,
+            Self::TopFrontRight => DDB_SPEAKER_TOP_FRONT_RIGHT,
+            Self::TopBackLeft => DDB_SPEAKER_TOP_BACK_LEFT,
+            Self::TopBackCenter => DDB_SPEAKER_TOP_BACK_CENTER,
+            Self::TopBackRight => DDB_SPEAKER_TOP_BACK_RIGHT,
+        }) as u32
+    }
+}
+
+/// Iterator returned by `WaveFormat::speakers`. Mask bits without a known
+/// speaker are skipped.
+#[derive(Debug, Clone)]
+pub struct Speakers {
+    mask: u32,
+}
+
+impl Iterator for Speakers {
+    type Item = Speaker;
+
+    fn next(&mut self) -> Option<Speaker> {
+        while self.mask != 0 {
+            let bit = self.mask.trailing_zeros() as usize;
+            self.mask &= self.mask - 1;
+            if let Some(speaker) = Speaker::ALL.get(bit) {
+                return Some(*speaker);
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn raw(samplerate: i32, bps: i32, channels: i32, is_float: i32) -> ddb_waveformat_t {
+        ddb_waveformat_t {
+            bps,
+            channels,
+            samplerate,
+            channelmask: 3,
+            is_float,
+            flags: 0,
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_formats() {
+        use DB_Format_Error::*;
+        assert_eq!(WaveFormat::new(0, 16, 2, false), Err(InvalidSampleRate(0)));
+        assert_eq!(WaveFormat::new(44100, 16, 0, false), Err(InvalidChannels(0)));
+        assert_eq!(WaveFormat::new(44100, 16, MAX_CHANNELS + 1, false), Err(InvalidChannels(33)));
+        assert_eq!(WaveFormat::new(44100, 12, 2, false), Err(InvalidBps(12)));
+        assert_eq!(WaveFormat::new(44100, 24, 2, true), Err(InvalidFloatBps(24)));
+
+        assert_eq!(WaveFormat::try_from(raw(-1, 16, 2, 0)), Err(InvalidSampleRate(-1)));
+        assert_eq!(WaveFormat::try_from(raw(44100, 16, -2, 0)), Err(InvalidChannels(-2)));
+        assert_eq!(WaveFormat::try_from(raw(44100, 0, 2, 0)), Err(InvalidBps(0)));
+        assert_eq!(WaveFormat::try_from(raw(44100, 16, 2, 1)), Err(InvalidFloatBps(16)));
+    }
+
+    #[test]
+    fn raw_round_trip() {
+        let fmt = WaveFormat::new(96000, 32, 2, true)
+            .unwrap()
+            .with_flags(DDB_WAVEFORMAT_FLAG_IS_DOP as u32);
+        let back = WaveFormat::try_from(ddb_waveformat_t::from(fmt)).unwrap();
+        assert_eq!(back, fmt);
+        assert!(back.is_dop());
+    }
+
+    #[test]
+    fn frames_and_bytes() {
+        let fmt = WaveFormat::new(48000, 24, 6, false).unwrap();
+        assert_eq!(fmt.bytes_per_sample(), 3);
+        assert_eq!(fmt.bytes_per_frame(), 18);
+        assert_eq!(fmt.frames_to_bytes(10), 180);
+        assert_eq!(fmt.bytes_to_frames(180), 10);
+        // Partial frames don't count
+        assert_eq!(fmt.bytes_to_frames(197), 10);
+        assert_eq!(fmt.align_bytes(197), 180);
+    }
+
+    #[test]
+    fn durations() {
+        let fmt = WaveFormat::new(48000, 16, 2, false).unwrap();
+        assert_eq!(fmt.duration_to_frames(Duration::from_millis(20)), 960);
+        assert_eq!(fmt.frames_to_duration(960), Duration::from_millis(20));
+        assert_eq!(fmt.duration_to_bytes(Duration::from_millis(20)), 3840);
+        assert_eq!(fmt.bytes_to_duration(3840), Duration::from_millis(20));
+
+        // Rates that don't divide a second evenly round down, and come back within a frame
+        let fmt = WaveFormat::default();
+        let frames = fmt.duration_to_frames(Duration::from_millis(10));
+        assert_eq!(frames, 441);
+        let duration = fmt.frames_to_duration(1000);
+        assert_eq!(fmt.duration_to_frames(duration), 999);
+    }
+
+    #[test]
+    fn speakers_follow_the_mask() {
+        let fmt = WaveFormat::new(48000, 16, 6, false).unwrap();
+        assert_eq!(fmt.speakers().collect::<Vec<_>>(), &Speaker::ALL[..6]);
+
+        let mask = Speaker::FrontCenter.mask() | Speaker::SideRight.mask() | (1 << 31);
+        let fmt = fmt.with_channelmask(mask);
+        assert_eq!(
+            fmt.speakers().collect::<Vec<_>>(),
+            [Speaker::FrontCenter, Speaker::SideRight]
+        );
+
+        let fmt = WaveFormat::new(48000, 16, MAX_CHANNELS, false).unwrap();
+        assert_eq!(fmt.channelmask(), u32::MAX);
+        assert_eq!(fmt.speakers().count(), Speaker::ALL.len());
+    }
+}
@@ -0,0 +1,83 @@
+use crate::*;
+
+/// What to do when the session manager corks, suspends or ducks our stream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PolicyAction {
+    /// Pause DeadBeef and resume once the policy releases the stream.
+    Pause,
+    /// Keep playing and leave volume changes made outside DeadBeef on the stream.
+    Duck,
+    /// Don't react to the policy at all.
+    Ignore,
+}
+
+/// The plugin's settings as last read from DeadBeef's config.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Config {
+    pub soundcard: String,
+    pub policy: PolicyAction,
+    pub fade_ms: u32,
+    pub crossfade_ms: u32,
+    pub channel_matrix: String,
+    pub verbose: bool,
+}
+
+deadbeef_sys::conf_settings! {
+    /// The settings as stored, each under `pipewirerust_` and its field name.
+    struct StoredConfig {
+        soundcard: String = "default".into(),
+        policy_action: u32 = 0,
+        fade_ms: u32 = 30,
+        crossfade_ms: u32 = 0,
+        channel_matrix: String = String::new(),
+        verbose: bool = false,
+    }
+}
+
+/// What it takes to get a config change to a running stream, cheapest first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ConfigChange {
+    /// Nothing the stream cares about.
+    None,
+    /// Picked up in place, by the callbacks or the channel matrix.
+    Update,
+    /// The stream has to be reconnected to another sink.
+    Retarget,
+    /// The stream has to be torn down and created again.
+    Rebuild,
+}
+
+impl Config {
+    pub fn load() -> Self {
+        let stored = StoredConfig::load("pipewirerust_");
+        let policy = match stored.policy_action {
+            1 => PolicyAction::Duck,
+            2 => PolicyAction::Ignore,
+            _ => PolicyAction::Pause,
+        };
+        // Same limits as the config dialog
+        Self {
+            soundcard: stored.soundcard,
+            policy,
+            fade_ms: stored.fade_ms.min(200),
+            crossfade_ms: stored.crossfade_ms.min(10000),
+            channel_matrix: stored.channel_matrix,
+            verbose: stored.verbose,
+        }
+    }
+
+    /// Compare against a newer snapshot.
+    pub fn change_to(&self, new: &Config) -> ConfigChange {
+        if self.soundcard != new.soundcard {
+            ConfigChange::Retarget
+        } else if self.policy != new.policy
+            || self.fade_ms != new.fade_ms
+            || self.crossfade_ms != new.crossfade_ms
+            || self.channel_matrix != new.channel_matrix
+        {
+            ConfigChange::Update
+        } else {
+            ConfigChange::None
+        }
+    }
+}
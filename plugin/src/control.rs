@@ -0,0 +1,154 @@
+use crate::*;
+
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::Duration;
+
+/// How often the control thread picks up events from the playback thread when idle.
+const EVENT_POLL: Duration = Duration::from_millis(50);
+
+/// Extra time on top of the plugin's own timeouts before a caller stops waiting for a reply.
+const REPLY_MARGIN: Duration = Duration::from_secs(1);
+
+/// Where the control thread sends the outcome of a command a callback waits for.
+pub type Reply<T = ()> = mpsc::SyncSender<Result<T, PluginError>>;
+
+/// Work queued by DeadBeef's callbacks for the control thread.
+pub enum Command {
+    Start,
+    Init(Reply<WaveFormat>),
+    Free,
+    Play,
+    Stop,
+    Pause,
+    Unpause,
+    SetFormat(WaveFormat),
+    LogDiagnostics,
+    Event(OutputEvent),
+    Shutdown(mpsc::SyncSender<()>),
+}
+
+/// The DeadBeef events the output reacts to. Picked out of a `DbEvent` while
+/// its payload is still valid, so they can be queued.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputEvent {
+    VolumeChanged,
+    /// The user is moving to another track, or seeking within this one.
+    Skip { seek: bool },
+    /// DeadBeef moved on to a track `length` long, unknown for streams.
+    SongChanged { length: Option<Duration> },
+    Seeked { position: Duration },
+    /// The user stopped playback, as opposed to the playlist running out.
+    Stop,
+    ConfigChanged,
+    ReinitSound,
+}
+
+impl OutputEvent {
+    pub fn from_event(event: &DbEvent) -> Option<Self> {
+        Some(match event {
+            DbEvent::VolumeChanged => Self::VolumeChanged,
+            DbEvent::Next | DbEvent::Prev | DbEvent::PlayNum(_) | DbEvent::PlayRandom => {
+                Self::Skip { seek: false }
+            }
+            DbEvent::Seek(_) => Self::Skip { seek: true },
+            DbEvent::SongChanged { to, .. } => Self::SongChanged {
+                length: to
+                    .and_then(|track| track.duration())
+                    .filter(|length| *length > 0.0)
+                    .map(Duration::from_secs_f32),
+            },
+            DbEvent::Seeked { playpos, .. } => Self::Seeked {
+                position: Duration::from_secs_f32(playpos.max(0.0)),
+            },
+            DbEvent::Stop => Self::Stop,
+            DbEvent::ConfigChanged => Self::ConfigChanged,
+            DbEvent::ReinitSound => Self::ReinitSound,
+            _ => return None,
+        })
+    }
+}
+
+/// Front end for DeadBeef's callbacks.
+///
+/// The `OutputPlugin` lives on a control thread of its own and is driven through a
+/// command queue, while its state is mirrored in a `StateCell`. Callbacks queue work
+/// and read the state, so a slow stop or a soundcard listing never holds up DeadBeef
+/// polling `getstate`. Only `init` waits for the outcome, with a bound, the rest
+/// find out about failures from the state.
+pub struct Controller {
+    state: Arc<StateCell>,
+    commands: mpsc::Sender<Command>,
+}
+
+impl Controller {
+    pub fn spawn() -> Result<Self, PluginError> {
+        let state = Arc::new(StateCell::new(OutputState::Stopped));
+        let (commands, receiver) = mpsc::channel();
+        let plugin_state = state.clone();
+        thread::Builder::new()
+            .name("pipewire-control".into())
+            .spawn(move || run(OutputPlugin::new(plugin_state), receiver))?;
+        Ok(Self { state, commands })
+    }
+
+    pub fn state(&self) -> OutputState {
+        self.state.get()
+    }
+
+    /// Show `state` to `getstate` before the control thread gets to the command
+    /// that leads there. The thread puts the real state back once it is done.
+    pub fn set_state(&self, state: OutputState) {
+        self.state.set(state);
+    }
+
+    /// Queue `command` without waiting for it to be carried out.
+    pub fn post(&self, command: Command) -> Result<(), PluginError> {
+        self.commands.send(command).map_err(|_| PluginError::ThreadGone)
+    }
+
+    /// Queue a command and wait for its outcome, giving up after `timeout`
+    /// plus some slack for the thread to get to it.
+    pub fn call<T>(&self, command: impl FnOnce(Reply<T>) -> Command, timeout: Duration) -> Result<T, PluginError> {
+        let (reply, result) = mpsc::sync_channel(1);
+        self.post(command(reply))?;
+        match result.recv_timeout(timeout + REPLY_MARGIN) {
+            Ok(result) => result,
+            Err(mpsc::RecvTimeoutError::Timeout) => Err(PluginError::NoReply),
+            Err(mpsc::RecvTimeoutError::Disconnected) => Err(PluginError::ThreadGone),
+        }
+    }
+
+    /// Open the output, stopping it first if need be.
+    /// Returns the format the output was opened with.
+    pub fn init(&self) -> Result<WaveFormat, PluginError> {
+        self.call(Command::Init, INIT_TIMEOUT + STOP_TIMEOUT)
+    }
+
+    /// Stop playback, shut PipeWire down and end the control thread.
+    /// Waits so the plugin isn't unloaded from under a running thread.
+    pub fn shutdown(&self) {
+        let (reply, done) = mpsc::sync_channel(1);
+        if self.post(Command::Shutdown(reply)).is_ok()
+            && done.recv_timeout(STOP_TIMEOUT + REPLY_MARGIN).is_err()
+        {
+            PluginError::ThreadStuck.log("Control thread did not shut down");
+        }
+    }
+}
+
+fn run(mut plugin: OutputPlugin, commands: mpsc::Receiver<Command>) {
+    loop {
+        match commands.recv_timeout(EVENT_POLL) {
+            Ok(Command::Shutdown(done)) => {
+                plugin.plugin_stop();
+                let _ = done.send(());
+                return;
+            }
+            Ok(command) => plugin.handle(command),
+            Err(mpsc::RecvTimeoutError::Timeout) => {}
+            Err(mpsc::RecvTimeoutError::Disconnected) => return,
+        }
+        plugin.poll_events();
+    }
+}
@@ -0,0 +1,150 @@
+use crate::*;
+
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+
+use pipewire::{registry::GlobalObject, spa::utils::dict::DictRef, types::ObjectType};
+
+/// Both ends of a link in the graph.
+#[derive(Debug, Clone, Copy)]
+struct Link {
+    output_node: u32,
+    output_port: u32,
+    input_node: u32,
+    input_port: u32,
+}
+
+/// Remembers DeadBeef's volume per sink, keyed by `node.name`, and restores it
+/// whenever our stream gets linked to a different sink.
+pub struct DeviceVolume {
+    node_id: Cell<Option<u32>>,
+    /// `node.name` of every node in the graph, by global id.
+    nodes: RefCell<HashMap<u32, String>>,
+    /// Every link in the graph, by global id.
+    links: RefCell<HashMap<u32, Link>>,
+    /// `node.name` of the sink we asked to be linked to, if not the default one.
+    target: RefCell<Option<String>>,
+    sink: RefCell<Option<String>>,
+}
+
+impl DeviceVolume {
+    pub fn new(target: &str) -> Self {
+        Self {
+            node_id: Cell::new(None),
+            nodes: RefCell::new(HashMap::new()),
+            links: RefCell::new(HashMap::new()),
+            target: RefCell::new(Self::target_name(target)),
+            sink: RefCell::new(None),
+        }
+    }
+
+    /// Feed from the registry `global` event.
+    /// Returns the new sink if this moved our stream to another one.
+    pub fn global(&self, global: &GlobalObject<&DictRef>) -> Option<String> {
+        let props = global.props?;
+        match global.type_ {
+            ObjectType::Node => {
+                if let Some(name) = props.get("node.name") {
+                    self.nodes.borrow_mut().insert(global.id, name.to_string());
+                }
+                None
+            }
+            ObjectType::Link => {
+                let id = |key: &str| props.get(key).and_then(|v| v.parse::<u32>().ok());
+                let link = Link {
+                    output_node: id("link.output.node")?,
+                    output_port: id("link.output.port").unwrap_or(u32::MAX),
+                    input_node: id("link.input.node")?,
+                    input_port: id("link.input.port").unwrap_or(u32::MAX),
+                };
+                self.links.borrow_mut().insert(global.id, link);
+                self.update()
+            }
+            _ => None,
+        }
+    }
+
+    /// Feed from the registry `global_remove` event.
+    pub fn global_remove(&self, id: u32) {
+        self.nodes.borrow_mut().remove(&id);
+        self.links.borrow_mut().remove(&id);
+    }
+
+    /// Drop everything known about the graph, ids are reused when the daemon restarts.
+    /// The sink is kept so reconnecting to the same one leaves the volume alone.
+    pub fn forget_graph(&self) {
+        self.node_id.set(None);
+        self.nodes.borrow_mut().clear();
+        self.links.borrow_mut().clear();
+    }
+
+    /// Our stream's node id, known once the stream is connected.
+    /// Returns the new sink if the node is linked to a different one.
+    pub fn set_node_id(&self, id: u32) -> Option<String> {
+        if self.node_id.replace(Some(id)) != Some(id) {
+            self.update()
+        } else {
+            None
+        }
+    }
+
+    /// The configured soundcard, `default` meaning whatever the session manager picks.
+    pub fn set_target(&self, target: &str) {
+        self.target.replace(Self::target_name(target));
+    }
+
+    fn target_name(target: &str) -> Option<String> {
+        (target != "default").then(|| target.to_string())
+    }
+
+    /// Remember `vol` for the sink we are playing on.
+    pub fn save(&self, vol: f32) {
+        if let Some(sink) = self.sink.borrow().as_ref() {
+            DeadBeef::conf_set_str(Self::conf_key(sink), vol.to_string());
+        }
+    }
+
+    fn update(&self) -> Option<String> {
+        let sink = self.linked_sink()?;
+
+        if self.sink.borrow().as_ref() == Some(&sink) {
+            return None;
+        }
+        if let Ok(vol) = DeadBeef::conf_get_str(Self::conf_key(&sink), "").parse::<f32>() {
+            DeadBeef::volume_set_amp(vol);
+        }
+        self.sink.replace(Some(sink.clone()));
+        Some(sink)
+    }
+
+    /// Name of the node our stream is linked to. A stream linked to several
+    /// nodes counts as playing on the target if that is one of them, and on
+    /// the one its first port goes to otherwise.
+    fn linked_sink(&self) -> Option<String> {
+        let node_id = self.node_id.get()?;
+        let links = self.links.borrow();
+        let nodes = self.nodes.borrow();
+        let target = self.target.borrow();
+
+        let linked: Vec<(&Link, &String)> = links
+            .values()
+            .filter(|link| link.output_node == node_id)
+            .filter_map(|link| Some((link, nodes.get(&link.input_node)?)))
+            .collect();
+        let on_target = linked.iter().find(|(_, name)| Some(*name) == target.as_ref());
+        let (_, name) = on_target.or_else(|| {
+            linked
+                .iter()
+                .min_by_key(|(link, _)| (link.output_port, link.input_port, link.input_node))
+        })?;
+        Some(name.to_string())
+    }
+
+    fn conf_key(sink: &str) -> String {
+        let name: String = sink
+            .chars()
+            .map(|c| if c.is_whitespace() { '_' } else { c })
+            .collect();
+        format!("pipewirerust_volume.{name}")
+    }
+}
@@ -0,0 +1,39 @@
+use crate::*;
+
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum PluginError {
+    #[error(transparent)]
+    Pipewire(#[from] pipewire::Error),
+    #[error("Unable to start playback thread: {0}")]
+    Spawn(#[from] std::io::Error),
+    #[error("Playback thread is not running")]
+    ThreadGone,
+    #[error("Playback thread panicked")]
+    ThreadPanicked,
+    #[error("Playback thread did not quit in time")]
+    ThreadStuck,
+    #[error("Stream failed: {0}")]
+    StreamFailed(String),
+    #[error("Timed out waiting for the stream to start")]
+    Timeout,
+    #[error("Control thread did not answer in time")]
+    NoReply,
+    #[error("PipeWire daemon did not answer in time")]
+    DaemonTimeout,
+    #[error("Unable to build format description")]
+    FormatPod,
+    #[error(transparent)]
+    Format(#[from] DB_Format_Error),
+}
+
+impl PluginError {
+    /// Report the error in DeadBeef's log, `context` says what we were doing.
+    pub fn log(&self, context: &str) {
+        DeadBeef::log_detailed(
+            DDB_LOG_LAYER_DEFAULT,
+            format!("Pipewire: {context}, {self}\n").as_str(),
+        );
+    }
+}
@@ -1,4 +1,4 @@
-use std::{ffi::{c_char, c_int, c_void}, sync::Mutex};
+use std::{cell::UnsafeCell, ffi::{c_char, c_int, c_void}};
 use once_cell::sync::Lazy;
 use deadbeef_sys::*;
 
@@ -7,13 +7,74 @@ mod utils;
 use lossycstring::LossyCString;
 use utils::*;
 
+mod error;
+use error::*;
+
+mod config;
+use config::*;
+
+mod sample;
+use sample::*;
+
+mod ramp;
+use ramp::*;
+
+mod remap;
+use remap::*;
+
+mod devicevolume;
+use devicevolume::*;
+
+mod pwthread;
+use pwthread::*;
+
+mod state;
+use state::*;
+
 mod plugin;
 use plugin::*;
 
-unsafe impl Send for OutputPlugin {}
+mod control;
+use control::*;
 
-static PLUGIN: Lazy<Mutex<OutputPlugin>> = Lazy::new(|| {
-    let x = DB_output_t {
+mod stats;
+use stats::*;
+
+const CONFIGDIALOG: &std::ffi::CStr = c"\
+property \"When the session manager corks or ducks playback\" select[3] pipewirerust_policy_action 0 \"Pause playback\" \"Duck only\" Ignore;\n\
+property \"Fade length on pause, stop and skip (ms, 0 disables)\" spinbtn[0,200,5] pipewirerust_fade_ms 30;\n\
+property \"Crossfade between tracks (ms, 0 disables)\" spinbtn[0,10000,100] pipewirerust_crossfade_ms 0;\n\
+property \"Channel remapping, e.g. FL=FR;FR=FL or FC=FL:0.7,FR:0.7\" entry pipewirerust_channel_matrix \"\";\n\
+property \"Verbose logging\" checkbox pipewirerust_verbose 0;\n\
+";
+
+/// The `DB_output_t` handed to DeadBeef, which keeps a pointer to it and reads `fmt`
+/// from it. Only the callbacks below write to it, on DeadBeef's threads.
+struct OutputDef(UnsafeCell<DB_output_t>);
+
+// Raw pointers in there are only static strings and function pointers
+unsafe impl Send for OutputDef {}
+unsafe impl Sync for OutputDef {}
+
+impl OutputDef {
+    /// `None` until a stream has been opened.
+    fn fmt(&self) -> Option<WaveFormat> {
+        WaveFormat::try_from(unsafe { (*self.0.get()).fmt }).ok()
+    }
+
+    fn set_fmt(&self, fmt: WaveFormat) {
+        unsafe { (*self.0.get()).fmt = fmt.into() }
+    }
+}
+
+impl DBPlugin for OutputDef {
+    fn get_plugin_ptr(&self) -> *const DB_output_t {
+        self.0.get()
+    }
+}
+
+static OUTPUT: Lazy<OutputDef> = Lazy::new(|| {
+    OutputDef(UnsafeCell::new(DB_output_t {
         init: Some(init),
         free: Some(free),
         play: Some(play),
@@ -43,94 +104,143 @@ static PLUGIN: Lazy<Mutex<OutputPlugin>> = Lazy::new(|| {
             stop: Some(plugin_stop),
             message: Some(message),
             connect: None,
-            get_actions: None,
+            get_actions: Some(get_actions),
             exec_cmdline: None,
             disconnect: None,
             command: None,
-            configdialog: std::ptr::null(),
+            configdialog: CONFIGDIALOG.as_ptr(),
             reserved1: 0,
             reserved2: 0,
             reserved3: 0,
         },
-    };
-    Mutex::new(OutputPlugin::new(x))
+    }))
 });
 
+/// Menu entry that dumps playback statistics to the log.
+struct ActionDef(UnsafeCell<DB_plugin_action_t>);
+
+unsafe impl Send for ActionDef {}
+unsafe impl Sync for ActionDef {}
+
+static DIAGNOSTICS_ACTION: Lazy<ActionDef> = Lazy::new(|| {
+    ActionDef(UnsafeCell::new(DB_plugin_action_t {
+        title: c"Help/Log PipeWire output diagnostics".as_ptr(),
+        name: c"pipewirerust_diagnostics".as_ptr(),
+        flags: DB_ACTION_COMMON | DB_ACTION_ADD_MENU,
+        callback: None,
+        next: std::ptr::null_mut(),
+        callback2: Some(log_diagnostics),
+    }))
+});
+
+static CONTROL: Lazy<Option<Controller>> = Lazy::new(|| {
+    Controller::spawn()
+        .inspect_err(|e| e.log("Unable to start control thread"))
+        .ok()
+});
+
+/// Queue `command` for the control thread, failures there end up in the log.
+fn post(command: Command) -> c_int {
+    match CONTROL.as_ref().map(|c| c.post(command)) {
+        Some(Ok(())) => 0,
+        Some(Err(e)) => {
+            e.log("Unable to queue command");
+            -1
+        }
+        None => -1,
+    }
+}
+
+/// Have `getstate` report `state` right away, ahead of the control thread.
+fn show_state(state: OutputState) {
+    if let Some(control) = CONTROL.as_ref() {
+        control.set_state(state);
+    }
+}
+
 extern "C" fn init() -> c_int {
     debug!("rustplug::init");
-    if let Ok(mut p) = PLUGIN.lock() {
-        p.init();
+    match CONTROL.as_ref().map(Controller::init) {
+        Some(Ok(fmt)) => {
+            OUTPUT.set_fmt(fmt);
+            0
+        }
+        Some(Err(e)) => {
+            e.log("Unable to open output");
+            -1
+        }
+        None => -1,
     }
-    0
 }
 
 extern "C" fn free() -> c_int {
     debug!("rustplug::free");
-    if let Ok(mut p) = PLUGIN.lock() {
-        p.free();
-    }
-    0
+    post(Command::Free)
 }
 
 extern "C" fn setformat(fmt: *mut ddb_waveformat_t) -> c_int {
     debug!("rustplug::setformat");
-    if let Ok(mut p) = PLUGIN.lock() {
-        unsafe { p.setformat(*fmt); }
+    if fmt.is_null() {
+        return -1;
     }
-    0
+    let fmt = unsafe { *fmt };
+    // No channels means DeadBeef has no preference
+    let fmt = if fmt.channels == 0 {
+        WaveFormat::default()
+    } else {
+        match WaveFormat::try_from(fmt) {
+            Ok(fmt) => fmt,
+            Err(e) => {
+                PluginError::from(e).log("Unable to set format");
+                return -1;
+            }
+        }
+    };
+    if OUTPUT.fmt() == Some(fmt) {
+        debug!("Format is equal. Not requesting change.");
+        return 0;
+    }
+    OUTPUT.set_fmt(fmt);
+    post(Command::SetFormat(fmt))
 }
 
 extern "C" fn play() -> c_int {
     debug!("rustplug::play");
-    if let Ok(mut p) = PLUGIN.lock() {
-        p.play();
-    }
-    0
+    show_state(OutputState::Playing);
+    post(Command::Play)
 }
 
-
 extern "C" fn stop() -> c_int {
     debug!("rustplug::stop");
-    if let Ok(mut p) = PLUGIN.lock() {
-        p.stop();
-    }
-    0
+    show_state(OutputState::Draining);
+    post(Command::Stop)
 }
 
 extern "C" fn pause() -> c_int {
     debug!("rustplug::pause");
-    if let Ok(mut p) = PLUGIN.lock() {
-        p.pause();
-    }
-    0
+    show_state(OutputState::Paused);
+    post(Command::Pause)
 }
 
 extern "C" fn unpause() -> c_int {
     debug!("rustplug::unpause");
-    if let Ok(mut p) = PLUGIN.lock() {
-        p.unpause();
-    }
-    0
+    show_state(OutputState::Playing);
+    post(Command::Unpause)
 }
 
 extern "C" fn getstate() -> ddb_playback_state_t {
-    if let Ok(p) = PLUGIN.lock() {
-        p.getstate()
-    } else {
-        DDB_PLAYBACK_STATE_STOPPED
-    }
+    CONTROL
+        .as_ref()
+        .map_or(DDB_PLAYBACK_STATE_STOPPED, |c| c.state().as_playback_state().as_raw())
 }
 
 extern "C" fn plugin_start() -> c_int {
-    if let Ok(mut p) = PLUGIN.lock(){
-        p.plugin_start();
-    }
-    0
+    post(Command::Start)
 }
 
 extern "C" fn plugin_stop() -> c_int {
-    if let Ok(mut p) = PLUGIN.lock(){
-        p.plugin_stop();
+    if let Some(control) = CONTROL.as_ref() {
+        control.shutdown();
     }
     0
 }
@@ -142,23 +252,36 @@ extern "C" fn enum_soundcards(
     userdata: *mut c_void,
 ) {
     unsafe {
-        if let Ok(p) = &PLUGIN.lock() {
-            
-            p.enum_soundcards(move|name, desc| {
-                let name = LossyCString::new(name);
-                let desc = LossyCString::new(desc);
-                callback.unwrap()(name.as_ptr(), desc.as_ptr(), userdata);
-            });
+        let Some(callback) = callback else {
+            return;
+        };
+        let result = plugin::enum_soundcards(move|name, desc| {
+            let name = LossyCString::new(name);
+            let desc = LossyCString::new(desc);
+            callback(name.as_ptr(), desc.as_ptr(), userdata);
+        });
+        if let Err(e) = result {
+            e.log("Unable to list soundcards");
         }
     }
 }
 
 
+extern "C" fn get_actions(_it: *mut DB_playItem_t) -> *mut DB_plugin_action_t {
+    DIAGNOSTICS_ACTION.0.get()
+}
+
+extern "C" fn log_diagnostics(_action: *mut DB_plugin_action_t, _ctx: ddb_action_context_t) -> c_int {
+    post(Command::LogDiagnostics)
+}
+
 extern "C" fn message(msgid: u32, ctx: usize, p1: u32, p2: u32) -> c_int {
-    if let Ok(mut p) = PLUGIN.lock() {
-        p.message(msgid, ctx, p1, p2);
+    // The payload is gone once we return, so decide what to queue right here
+    let event = unsafe { DbEvent::decode(msgid, ctx, p1, p2) };
+    match OutputEvent::from_event(&event) {
+        Some(event) => post(Command::Event(event)),
+        None => 0,
     }
-    0
 }
 
 #[no_mangle]
@@ -168,5 +291,5 @@ extern "C" fn message(msgid: u32, ctx: usize, p1: u32, p2: u32) -> c_int {
 pub unsafe extern "C" fn ddb_output_pw_rust_load(
     api: *const DB_functions_t,
 ) -> *mut DB_plugin_t {
-    DeadBeef::init_from_ptr(api, &*PLUGIN.lock().unwrap())
+    DeadBeef::init_from_ptr(api, &*OUTPUT)
 }
@@ -1,552 +1,525 @@
 use crate::*;
 
 use std::rc::Rc;
-use std::{cell::Cell, thread};
+use std::sync::Arc;
+use std::time::Duration;
 
-use pipewire::{
-    context::Context,
-    core::PW_ID_CORE,
-    main_loop::MainLoop,
-    properties::properties,
-    spa::utils::Direction,
-    stream::{self, StreamFlags},
-};
+use pipewire::{context::Context, core::PW_ID_CORE, main_loop::MainLoop};
 
-pub struct OutputPlugin {
-    plugin: DB_output_t,
+/// How long `init` waits for the stream to connect before reporting failure.
+pub const INIT_TIMEOUT: Duration = Duration::from_secs(3);
 
-    state: PlaybackState,
-    thread: Option<PlaybackThread>,
+/// How long stopping waits for the playback thread, fading out included.
+pub const STOP_TIMEOUT: Duration = Duration::from_secs(2);
 
-    requested_fmt: Option<ddb_waveformat_t>,
-}
-
-struct PlaybackThread {
-    handle: thread::JoinHandle<()>,
-    sender: pipewire::channel::Sender<PwThreadMessage>,
-}
+/// How long listing the sinks waits for the daemon, the preferences dialog is waiting.
+const ENUM_TIMEOUT: Duration = Duration::from_secs(2);
 
-#[derive(Debug)]
-enum PwThreadMessage {
-    Terminate,
-    Pause,
-    Unpause,
-    SetFmt {
-        format: ddb_waveformat_t,
-        state: PlaybackState,
-    },
-    SetVol {
-        newvol: f32,
-    },
-    SetTitle(String),
+/// The output's mutable side, owned by the control thread.
+pub struct OutputPlugin {
+    state: OutputState,
+    /// Mirrors `state` for `getstate`.
+    state_cell: Arc<StateCell>,
+    thread: Option<PlaybackThread>,
+    /// `pipewire::init` has been called and not undone yet.
+    pw_initialized: bool,
+
+    requested_fmt: Option<WaveFormat>,
+    /// DeadBeef asked us to pause, as opposed to the graph suspending the stream.
+    player_paused: bool,
+    /// We paused DeadBeef because the stream got corked, so it resumes once released.
+    cork_paused: bool,
+    /// Length of the current track, passed on to the playback thread with the position.
+    track_length: Option<Duration>,
+    /// The user asked to stop, so audio held back for a crossfade isn't played out.
+    user_stop: bool,
+    /// Settings as of the last `DB_EV_CONFIGCHANGED`, to tell what changed.
+    config: Config,
+    /// Compiled once, evaluated on every track change.
+    media_name: Option<TitleFormat>,
+
+    /// Kept after stopping so the last session can still be reported on.
+    stats: Option<Arc<Stats>>,
+    /// Rate and channels PipeWire settled on.
+    negotiated: Option<(u32, u32)>,
+    latency: Option<Duration>,
+    sink: Option<String>,
 }
 
-impl PlaybackThread {
-    pub fn new(init_fmt: ddb_waveformat_t) -> Self {
-        let (sender, receiver) = pipewire::channel::channel();
+impl OutputPlugin {
+    pub fn new(state_cell: Arc<StateCell>) -> Self {
         Self {
-            handle: thread::spawn(move || pw_thread_main(init_fmt, receiver)),
-            sender,
+            state: OutputState::Stopped,
+            state_cell,
+            thread: None,
+            pw_initialized: false,
+            requested_fmt: None,
+            player_paused: false,
+            cork_paused: false,
+            track_length: None,
+            user_stop: false,
+            config: Config::load(),
+            media_name: None,
+            stats: None,
+            negotiated: None,
+            latency: None,
+            sink: None,
         }
     }
 
-    pub fn join(self) -> thread::Result<()> {
-        self.handle.join()
-    }
-
-    pub fn msg(&self, msg: PwThreadMessage) {
-        self.sender
-            .send(msg)
-            .expect("Unable to send message to thread!")
-    }
-}
-
-impl DBPlugin for OutputPlugin {
-    fn get_plugin_ptr(&self) -> *const DB_output_t {
-        &self.plugin as *const DB_output_t
+    /// Carry out a command queued by one of DeadBeef's callbacks.
+    pub fn handle(&mut self, command: Command) {
+        self.dispatch(command);
+        // Undo the callback's guess if things went another way
+        self.state_cell.set(self.state);
     }
-}
 
-impl OutputPlugin {
-    pub fn new(plugin: DB_output_t) -> Self {
-        Self {
-            plugin,
-            state: PlaybackState::Stopped,
-            thread: None,
-            requested_fmt: None,
+    fn dispatch(&mut self, command: Command) {
+        let (result, context) = match command {
+            Command::Start => {
+                self.plugin_start();
+                return;
+            }
+            Command::Init(reply) => {
+                let _ = reply.send(self.init());
+                return;
+            }
+            Command::Free => (self.free(), "Unable to close output"),
+            Command::Play => (self.play(), "Unable to start playback"),
+            Command::Stop => (self.stop(), "Unable to stop playback"),
+            Command::SetFormat(fmt) => (self.setformat(fmt), "Unable to change format"),
+            Command::Pause => (self.pause(), "Unable to pause playback"),
+            Command::Unpause => (self.unpause(), "Unable to resume playback"),
+            Command::Event(event) => (self.message(event), "Unable to handle event"),
+            Command::LogDiagnostics => {
+                self.log_diagnostics();
+                return;
+            }
+            Command::Shutdown(_) => {
+                self.plugin_stop();
+                return;
+            }
+        };
+        if let Err(e) = result {
+            e.log(context);
         }
     }
 
     pub fn plugin_start(&mut self) {
+        set_verbose(self.config.verbose);
+        self.media_name = TitleFormat::compile(MEDIA_NAME_FORMAT)
+            .inspect_err(|e| debug!("Unable to compile media name format: {e}"))
+            .ok();
         pipewire::init();
+        self.pw_initialized = true;
     }
+
+    /// DeadBeef may quit in the middle of playback, so shut the thread down
+    /// before pulling PipeWire out from under it.
     pub fn plugin_stop(&mut self) {
-        unsafe {
-            pipewire::deinit();
+        // Free it while the API is still around
+        self.media_name = None;
+        // DeadBeef is waiting on us, don't play anything out
+        self.user_stop = true;
+        match self.stop() {
+            Ok(()) => {}
+            Err(PluginError::ThreadStuck) => {
+                // Deinitializing underneath a live main loop would crash, leak it instead
+                PluginError::ThreadStuck.log("Not shutting down PipeWire");
+                return;
+            }
+            Err(e) => e.log("Error stopping playback"),
+        }
+        if std::mem::take(&mut self.pw_initialized) {
+            unsafe {
+                pipewire::deinit();
+            }
         }
     }
 
-    #[allow(unused)]
-    pub fn message(&mut self, msgid: u32, ctx: usize, p1: u32, p2: u32) {
-        match msgid {
-            DB_EV_VOLUMECHANGED => self.msgtothread(PwThreadMessage::SetVol {
+    pub fn message(&mut self, event: OutputEvent) -> Result<(), PluginError> {
+        self.poll_events();
+        match event {
+            OutputEvent::VolumeChanged => self.msgtothread(PwThreadMessage::SetVol {
                 newvol: DeadBeef::volume_get_amp(),
             }),
-            DB_EV_SONGCHANGED => {
-                if let Ok(media_name) = DeadBeef::titleformat("[%artist% - ]%title%") {
-                    self.msgtothread(PwThreadMessage::SetTitle(media_name))
+            OutputEvent::Skip { seek } => {
+                if self.state == OutputState::Playing {
+                    self.msgtothread(PwThreadMessage::SkipBegin { seek })
+                } else {
+                    Ok(())
+                }
+            }
+            OutputEvent::SongChanged { length } => {
+                self.track_length = length;
+                if let Some(media_name) = self.media_name() {
+                    self.msgtothread(PwThreadMessage::SetTitle(media_name))?;
                 }
+                self.msgtothread(PwThreadMessage::TrackPosition {
+                    length,
+                    position: Duration::ZERO,
+                })?;
+                self.msgtothread(PwThreadMessage::TrackChanged)
+            }
+            OutputEvent::Seeked { position } => {
+                self.msgtothread(PwThreadMessage::TrackPosition {
+                    length: self.track_length,
+                    position,
+                })?;
+                self.msgtothread(PwThreadMessage::SkipEnd)
+            }
+            OutputEvent::Stop => {
+                self.user_stop = true;
+                Ok(())
+            }
+            OutputEvent::ConfigChanged => {
+                let config = Config::load();
+                let change = self.config.change_to(&config);
+                self.reconfigure(config, change)
             }
-            _ => {}
+            // Rebuild the stream in place, keeping the output playing or paused
+            OutputEvent::ReinitSound => self.reconfigure(Config::load(), ConfigChange::Rebuild),
         }
     }
 
-    fn msgtothread(&self, msg: PwThreadMessage) {
-        if let Some(s) = self.thread.as_ref() {
-            s.msg(msg);
-        }
+    fn media_name(&self) -> Option<String> {
+        let track = DeadBeef::current_track().ok()?;
+        let name = self.media_name.as_ref()?.eval(&track).ok()?;
+        (!name.is_empty()).then_some(name)
     }
 
-    pub fn init(&mut self) -> i32 {
-        if self.requested_fmt.is_none() {
-            self.requested_fmt = Some(get_default_waveformat());
+    fn reconfigure(&mut self, config: Config, change: ConfigChange) -> Result<(), PluginError> {
+        set_verbose(config.verbose);
+        self.config = config.clone();
+        if change == ConfigChange::None {
+            return Ok(());
         }
-
-        self.plugin.fmt = self.requested_fmt.unwrap();
-
-        self.thread = Some(PlaybackThread::new(self.plugin.fmt));
-
-        self.state = PlaybackState::Stopped;
-        0
+        debug!("Applying config change: {change:?}");
+        self.msgtothread(PwThreadMessage::Reconfigure { config, change })
     }
 
-    pub fn play(&mut self) {
-        if self.thread.is_none() {
-            self.init();
+    fn msgtothread(&mut self, msg: PwThreadMessage) -> Result<(), PluginError> {
+        let Some(thread) = self.thread.as_ref() else {
+            return Ok(());
+        };
+        let result = thread.msg(msg);
+        if result.is_err() {
+            // The thread has quit on its own, nothing is playing anymore
+            self.thread = None;
+            self.set_state(OutputState::Error);
         }
-        self.state = PlaybackState::Playing;
+        result
     }
 
-    pub fn stop(&mut self) {
-        self.msgtothread(PwThreadMessage::Terminate);
-        if let Some(t) = self.thread.take() {
-            match t.join() {
-                Ok(_) => (),
-                Err(_) => {
-                    DeadBeef::log_detailed(DDB_LOG_LAYER_INFO, "Playback thread lingering!");
-                }
-            }
+    /// Move to `next` if that is a valid step from where we are.
+    fn set_state(&mut self, next: OutputState) -> bool {
+        let current = self.state;
+        if current == next {
+            return true;
+        }
+        if !current.can_transition(next) {
+            debug!("Ignoring state change {current:?} -> {next:?}");
+            return false;
         }
-        self.state = PlaybackState::Stopped;
-        self.thread = None;
+        debug!("State {current:?} -> {next:?}");
+        self.state = next;
+        self.state_cell.set(next);
+        true
     }
 
-    pub fn free(&mut self) {
-        self.stop();
+    /// Catch up with what the playback thread has reported.
+    pub fn poll_events(&mut self) {
+        let Some(thread) = self.thread.as_ref() else {
+            return;
+        };
+        let events: Vec<PwThreadEvent> = thread.events().collect();
+        for event in events {
+            self.handle_event(event);
+        }
     }
 
-    pub fn pause(&mut self) {
-        if self.thread.is_none() {
-            self.init();
+    fn handle_event(&mut self, event: PwThreadEvent) {
+        match event {
+            // DeadBeef drives playing and pausing, but the graph can suspend the
+            // stream under us, and a reconnected stream comes back on its own.
+            PwThreadEvent::Paused => {
+                if matches!(self.state, OutputState::Starting | OutputState::Playing) {
+                    self.set_state(OutputState::Paused);
+                }
+            }
+            PwThreadEvent::Streaming => {
+                if !self.player_paused && matches!(self.state, OutputState::Starting | OutputState::Paused) {
+                    self.set_state(OutputState::Playing);
+                }
+            }
+            PwThreadEvent::Format { rate, channels } => {
+                DeadBeef::log_detailed(
+                    DDB_LOG_LAYER_INFO,
+                    format!("Pipewire: Stream running at {rate} Hz, {channels} channels\n").as_str(),
+                );
+                self.negotiated = Some((rate, channels));
+            }
+            PwThreadEvent::Latency(latency) => {
+                DeadBeef::log_detailed(
+                    DDB_LOG_LAYER_INFO,
+                    format!("Pipewire: Output latency is {} ms\n", latency.as_millis()).as_str(),
+                );
+                self.latency = Some(latency);
+            }
+            PwThreadEvent::DeviceMoved(sink) => {
+                DeadBeef::log_detailed(
+                    DDB_LOG_LAYER_INFO,
+                    format!("Pipewire: Playing on {sink}\n").as_str(),
+                );
+                self.sink = Some(sink);
+            }
+            PwThreadEvent::Corked(true) => {
+                // Reported right after the stream went to paused
+                if !self.player_paused && matches!(self.state, OutputState::Playing | OutputState::Paused) {
+                    send(PlayerCommand::Pause);
+                    self.cork_paused = true;
+                }
+            }
+            PwThreadEvent::Corked(false) => {
+                // Leave a pause the user asked for alone
+                if std::mem::take(&mut self.cork_paused) && self.player_paused {
+                    send(PlayerCommand::PlayCurrent);
+                }
+            }
+            PwThreadEvent::Failed(e) => {
+                debug!("Playback thread failed: {e}");
+                if !matches!(self.state, OutputState::Stopped | OutputState::Error) {
+                    self.set_state(OutputState::Error);
+                    send(PlayerCommand::Stop);
+                }
+            }
         }
-
-        self.msgtothread(PwThreadMessage::Pause);
-        self.state = PlaybackState::Paused;
     }
 
-    pub fn unpause(&mut self) {
+    /// Start the playback thread and wait for its stream to come up, so DeadBeef
+    /// finds out right away if there is no usable output.
+    /// Returns the format the stream was opened with.
+    pub fn init(&mut self) -> Result<WaveFormat, PluginError> {
+        if self.thread.is_some() {
+            // Reopening, there is nothing to play out
+            self.user_stop = true;
+            self.stop()?;
+        }
+        let fmt = *self.requested_fmt.get_or_insert_with(WaveFormat::default);
+        self.set_state(OutputState::Starting);
+
+        let thread = PlaybackThread::new(fmt, self.config.clone())?;
+        self.stats = Some(thread.stats());
+        self.negotiated = None;
+        self.latency = None;
+        let events = match thread.wait_ready(INIT_TIMEOUT) {
+            Ok(events) => events,
+            Err(e) => {
+                // Don't leave a half started thread behind
+                let _ = thread.msg(PwThreadMessage::Terminate { drain: false });
+                if let Err(e) = thread.join_timeout(STOP_TIMEOUT) {
+                    e.log("Unable to clean up after failed start");
+                }
+                self.set_state(OutputState::Error);
+                return Err(e);
+            }
+        };
+        self.thread = Some(thread);
+        for event in events {
+            self.handle_event(event);
+        }
+        // Picks up a track that was already playing, for crossfading out of it
+        if self.track_length.is_some() {
+            let position = Duration::from_secs_f32(DeadBeef::streamer_get_playpos().max(0.0));
+            self.msgtothread(PwThreadMessage::TrackPosition {
+                length: self.track_length,
+                position,
+            })?;
+        }
+        Ok(fmt)
+    }
+
+    pub fn play(&mut self) -> Result<(), PluginError> {
+        self.poll_events();
+        self.player_paused = false;
+        self.cork_paused = false;
+        self.user_stop = false;
+        match self.state {
+            OutputState::Playing => return Ok(()),
+            OutputState::Stopped | OutputState::Error => {
+                self.init()?;
+            }
+            OutputState::Starting | OutputState::Paused | OutputState::Draining => {}
+        }
         if self.thread.is_none() {
-            self.init();
+            // Paused before anything was started
+            self.init()?;
         }
-        if self.state == PlaybackState::Paused {
-            self.msgtothread(PwThreadMessage::Unpause);
-            self.state = PlaybackState::Playing;
+        // The stream may have been (re)created inactive while paused
+        self.msgtothread(PwThreadMessage::Unpause)?;
+        self.set_state(OutputState::Playing);
+        Ok(())
+    }
+
+    pub fn stop(&mut self) -> Result<(), PluginError> {
+        self.player_paused = false;
+        self.cork_paused = false;
+        let Some(thread) = self.thread.take() else {
+            self.set_state(OutputState::Stopped);
+            return Ok(());
+        };
+        self.set_state(OutputState::Draining);
+        // At the end of the playlist the last track is played out to its end
+        let drain = !std::mem::take(&mut self.user_stop);
+        let timeout = if drain {
+            STOP_TIMEOUT + Duration::from_millis(self.config.crossfade_ms as u64)
+        } else {
+            STOP_TIMEOUT
+        };
+        // If the thread has already quit on its own the join sorts it out
+        let _ = thread.msg(PwThreadMessage::Terminate { drain });
+        let joined = thread.join_timeout(timeout);
+        self.set_state(OutputState::Stopped);
+        joined
+    }
+
+    pub fn free(&mut self) -> Result<(), PluginError> {
+        self.stop()
+    }
+
+    pub fn pause(&mut self) -> Result<(), PluginError> {
+        self.poll_events();
+        self.player_paused = true;
+        match self.state {
+            // Already paused may only mean the graph suspended the stream,
+            // it must not come back by itself
+            OutputState::Starting | OutputState::Playing | OutputState::Paused => {
+                self.msgtothread(PwThreadMessage::Pause)?;
+                self.set_state(OutputState::Paused);
+            }
+            // Remember it, the thread gets started when playback resumes
+            OutputState::Stopped => {
+                self.set_state(OutputState::Paused);
+            }
+            OutputState::Draining | OutputState::Error => {}
         }
+        Ok(())
     }
 
-    pub fn getstate(&self) -> ddb_playback_state_e {
-        self.state.as_raw()
+    pub fn unpause(&mut self) -> Result<(), PluginError> {
+        self.play()
     }
 
-    pub fn setformat(&mut self, fmt: ddb_waveformat_t) {
-        if fmt == self.plugin.fmt {
-            debug!("Format is equal. Not requesting change.");
-            return;
+    /// Write what we know about the current or last session to the log, for bug reports.
+    fn log_diagnostics(&mut self) {
+        self.poll_events();
+        let mut report = format!(
+            "Pipewire diagnostics\nState {:?}, target {}, linked to {}\n",
+            self.state,
+            self.config.soundcard,
+            self.sink.as_deref().unwrap_or("nothing"),
+        );
+        if let Some(fmt) = self.requested_fmt {
+            report += &format!("Requested {fmt}\n");
         }
-        self.plugin.fmt = if fmt.channels == 0 {
-            get_default_waveformat()
-        } else {
-            fmt
-        };
-        self.requested_fmt = Some(self.plugin.fmt);
+        if let Some((rate, channels)) = self.negotiated {
+            report += &format!("Negotiated {rate} Hz, {channels} channels\n");
+        }
+        if let Some(latency) = self.latency {
+            report += &format!("Latency {} ms\n", latency.as_millis());
+        }
+        match &self.stats {
+            Some(stats) => report += &stats.report(),
+            None => report += "No playback session yet\n",
+        }
+        DeadBeef::log_detailed(DDB_LOG_LAYER_DEFAULT, &report);
+    }
+
+    /// `fmt` has already been checked against the current format by the caller.
+    pub fn setformat(&mut self, fmt: WaveFormat) -> Result<(), PluginError> {
+        self.requested_fmt = Some(fmt);
         print_db_format(fmt);
         self.msgtothread(PwThreadMessage::SetFmt {
             format: fmt,
-            state: self.state,
-        });
-    }
-
-    pub fn enum_soundcards<F>(&self, callback: F)
-    where
-        F: Fn(&str, &str) + 'static,
-    {
-        let mainloop = MainLoop::new(None).expect("Failed to create mainloop");
-        let context = Context::new(&mainloop).expect("Failed to create context");
-        let core = context.connect(None).expect("Failed to connect to remote");
-        let registry = core.get_registry().expect("Failed to get registry");
-
-        // Register a callback to the `global` event on the registry, which notifies of any new global objects
-        // appearing on the remote.
-        // The callback will only get called as long as we keep the returned listener alive.
-        let _listener = registry
-            .add_listener_local()
-            .global(move |global| {
-                if let Some(props) = &global.props {
-                    let media_class = props.get("media.class").unwrap_or("");
-                    if media_class.eq("Audio/Sink") || media_class.eq("Audio/Duplex") {
-                        let name = props.get("node.name").unwrap_or("");
-                        if !name.is_empty() {
-                            callback(name, props.get("node.description").unwrap_or(""));
-                        }
-                    }
-                }
-            })
-            .register();
-
-        let done = Rc::new(std::cell::Cell::new(false));
-        let pending = core.sync(0).expect("Error sync");
-        let mainloop_clone = mainloop.clone();
-        let done_clone = done.clone();
-
-        let _core = core
-            .add_listener_local()
-            .done(move |id, seq| {
-                if id == PW_ID_CORE && seq == pending {
-                    done_clone.set(true);
-                    mainloop_clone.quit()
-                }
-            })
-            .register();
-
-        while !done.get() {
-            mainloop.run();
-        }
+            state: self.state.as_playback_state(),
+        })
     }
 }
 
-fn make_channel_map(channels: u32) -> [u32; 64] {
-    let mut position = [0; 64];
-    if channels == 1 {
-        position[0] = libspa_sys::SPA_AUDIO_CHANNEL_MONO;
-    }
-    if channels >= 2 {
-        position[0] = libspa_sys::SPA_AUDIO_CHANNEL_FL;
-        position[1] = libspa_sys::SPA_AUDIO_CHANNEL_FR;
-    }
-    if channels >= 3 {
-        position[2] = libspa_sys::SPA_AUDIO_CHANNEL_FC;
-    }
-    if channels >= 4 {
-        position[3] = libspa_sys::SPA_AUDIO_CHANNEL_LFE;
-    }
-    if channels >= 6 {
-        position[4] = libspa_sys::SPA_AUDIO_CHANNEL_RL;
-        position[5] = libspa_sys::SPA_AUDIO_CHANNEL_RR;
-    }
-    if channels >= 8 {
-        position[6] = libspa_sys::SPA_AUDIO_CHANNEL_FLC;
-        position[7] = libspa_sys::SPA_AUDIO_CHANNEL_FRC;
-    }
-    if channels >= 9 {
-        position[8] = libspa_sys::SPA_AUDIO_CHANNEL_RC;
-    }
-    if channels >= 11 {
-        position[9] = libspa_sys::SPA_AUDIO_CHANNEL_SL;
-        position[10] = libspa_sys::SPA_AUDIO_CHANNEL_SR;
-    }
-    if channels >= 12 {
-        position[11] = libspa_sys::SPA_AUDIO_CHANNEL_TC;
-    }
-    if channels >= 15 {
-        position[12] = libspa_sys::SPA_AUDIO_CHANNEL_TFL;
-        position[13] = libspa_sys::SPA_AUDIO_CHANNEL_TFC;
-        position[14] = libspa_sys::SPA_AUDIO_CHANNEL_TFR;
-    }
-    if channels >= 18 {
-        position[15] = libspa_sys::SPA_AUDIO_CHANNEL_TRL;
-        position[16] = libspa_sys::SPA_AUDIO_CHANNEL_TRC;
-        position[17] = libspa_sys::SPA_AUDIO_CHANNEL_TRR;
+/// Ask the player to do something. Only fails with DeadBeef's queue backed up.
+fn send(command: PlayerCommand) {
+    if let Err(e) = DeadBeef::send(command) {
+        DeadBeef::log_detailed(
+            DDB_LOG_LAYER_DEFAULT,
+            format!("Pipewire: Unable to send {command:?}, {e}\n").as_str(),
+        );
     }
-
-    position
 }
 
-fn create_audio_format_pod(
-    format: pipewire::spa::param::audio::AudioFormat,
-    channels: u32,
-    rate: u32,
-    buffer: &mut Vec<u8>,
-) -> &pipewire::spa::pod::Pod {
-    let mut audio_info = pipewire::spa::param::audio::AudioInfoRaw::new();
-    audio_info.set_format(format);
-    audio_info.set_rate(rate);
-    audio_info.set_channels(channels);
-
-    audio_info.set_position(make_channel_map(channels));
-
-    let values = pipewire::spa::pod::serialize::PodSerializer::serialize(
-        std::io::Cursor::new(buffer),
-        &pipewire::spa::pod::Value::Object(pipewire::spa::pod::Object {
-            type_: libspa_sys::SPA_TYPE_OBJECT_Format,
-            id: libspa_sys::SPA_PARAM_EnumFormat,
-            properties: audio_info.into(),
-        }),
-    )
-    .unwrap()
-    .0
-    .into_inner();
-
-    pipewire::spa::pod::Pod::from_bytes(values).unwrap()
-}
-
-fn pw_thread_main(
-    init_fmt: ddb_waveformat_t,
-    pw_receiver: pipewire::channel::Receiver<PwThreadMessage>,
-) {
-    let mainloop = MainLoop::new(None).expect("Failed to create mainloop");
-    let client_props = properties! {
-        *pipewire::keys::APP_NAME => "DeadBeef",
-        *pipewire::keys::APP_ID => "music.player.deadbeef",
-        *pipewire::keys::APP_ICON_NAME => "deadbeef"
-    };
-    let context = Context::new(&mainloop).expect("Context");
-    let core = context.connect(Some(client_props)).expect("Core");
-
-    let device = DeadBeef::conf_get_str("pipewirerust_soundcard", "default");
-
-    let mut props = properties! {
-        *pipewire::keys::MEDIA_TYPE => "Audio",
-        *pipewire::keys::MEDIA_CATEGORY => "Playback",
-        *pipewire::keys::MEDIA_ROLE => "Music",
-        *pipewire::keys::NODE_NAME => "DeadBeef",
-        *pipewire::keys::APP_NAME => "DeadBeef",
-        *pipewire::keys::APP_ID => "music.player.deadbeef",
-        *pipewire::keys::APP_ICON_NAME => "deadbeef",
-        "node.latency" => "1200/48000",
-    };
-
-    let s = format!("1/{}", init_fmt.samplerate);
-    props.insert("node.rate", s);
-
-    if !device.eq("default") {
-        props.insert(*pipewire::keys::TARGET_OBJECT, device);
-    }
-
-    if let Ok(media_name) = DeadBeef::titleformat("[%artist% - ]%title%") {
-        props.insert(*pipewire::keys::MEDIA_NAME, media_name);
-    }
-
-    let ourdisconnect = Rc::new(Cell::new(false));
-    let fmt = Rc::new(Cell::new(init_fmt));
-
-    let stream: stream::Stream = match pipewire::stream::Stream::new(&core, "deadbeef", props) {
-        Ok(a) => a,
-        Err(e) => {
-            DeadBeef::log_detailed(
-                DDB_LOG_LAYER_DEFAULT,
-                format!("Pipewire: Unable to create stream, {}\n", e.to_string()).as_str(),
-            );
-            DeadBeef::sendmessage(DB_EV_STOP, 0, 0, 0);
-            return;
-        }
-    };
-
-    let _listener = stream
-        .add_local_listener::<()>()
-        .state_changed({
-            let ourdisconnect = ourdisconnect.clone();
-            move |_stream, _userdata, _old, new| {
-                debug!("State changed: {_old:?} -> {new:?}");
-                match new {
-                    pipewire::stream::StreamState::Error(x) => {
-                        let msg = format!("Pipewire playback error: {x}");
-                        DeadBeef::log_detailed(DDB_LOG_LAYER_DEFAULT, &msg);
-                        DeadBeef::sendmessage(DB_EV_STOP, 0, 0, 0);
+/// List the sinks. This runs a main loop of its own and shares nothing with playback,
+/// so it is called straight from DeadBeef's callback.
+pub fn enum_soundcards<F>(callback: F) -> Result<(), PluginError>
+where
+    F: Fn(&str, &str) + 'static,
+{
+    let mainloop = MainLoop::new(None)?;
+    let context = Context::new(&mainloop)?;
+    let core = context.connect(None)?;
+    let registry = core.get_registry()?;
+
+    // Register a callback to the `global` event on the registry, which notifies of any new global objects
+    // appearing on the remote.
+    // The callback will only get called as long as we keep the returned listener alive.
+    let _listener = registry
+        .add_listener_local()
+        .global(move |global| {
+            if let Some(props) = &global.props {
+                let media_class = props.get("media.class").unwrap_or("");
+                if media_class.eq("Audio/Sink") || media_class.eq("Audio/Duplex") {
+                    let name = props.get("node.name").unwrap_or("");
+                    if !name.is_empty() {
+                        callback(name, props.get("node.description").unwrap_or(""));
                     }
-                    pipewire::stream::StreamState::Unconnected => {
-                        if !ourdisconnect.get() {
-                            DeadBeef::log_detailed(DDB_LOG_LAYER_DEFAULT, "Pipewire disconnected.");
-                            DeadBeef::sendmessage(DB_EV_STOP, 0, 0, 0);
-                        }
-                    }
-                    pipewire::stream::StreamState::Connecting => {
-                        ourdisconnect.set(false);
-                    }
-                    _ => {}
                 }
             }
         })
-        .process({
-            let fmt = fmt.clone();
-            let ourdisconnect = ourdisconnect.clone();
-            move |stream, _userdata| {
-                let fmt = fmt.get();
-
-                // This prevents glitches during format changes
-                if ourdisconnect.get() {
-                    return;
-                }
+        .register();
 
-                match stream.dequeue_buffer() {
-                    None => debug!("No buffer received"),
-                    Some(mut buffer) => {
-                        let req = buffer.requested();
-                        let datas = buffer.datas_mut();
-
-                        let maxsize = datas[0].as_raw().maxsize as i32;
-                        if let Some(d) = datas[0].data() {
-                            let stride = fmt.channels * (fmt.bps / 8);
-
-                            let len = if req > 0 {
-                                req as i32 * stride
-                            } else {
-                                let buffersize = 25 * fmt.samplerate / 1000;
-                                buffersize.min(maxsize / stride)
-                            };
-
-                            let bytesread = if DeadBeef::streamer_ok_to_read(-1) > 0 {
-                                DeadBeef::streamer_read(d.as_mut_ptr() as *mut c_void, len as usize)
-                            } else {
-                                0
-                            };
-
-                            if bytesread < len {
-                                d[bytesread as usize..].fill(0);
-                            }
-
-                            *datas[0].chunk_mut().size_mut() = bytesread as u32;
-                            *datas[0].chunk_mut().offset_mut() = 0;
-                            *datas[0].chunk_mut().stride_mut() = stride;
-                        }
-                    }
-                };
+    let done = Rc::new(std::cell::Cell::new(false));
+    let pending = core.sync(0)?;
+    let mainloop_clone = mainloop.clone();
+    let done_clone = done.clone();
+
+    let _core = core
+        .add_listener_local()
+        .done(move |id, seq| {
+            if id == PW_ID_CORE && seq == pending {
+                done_clone.set(true);
+                mainloop_clone.quit()
             }
         })
-        .control_info(
-            move |_stream, _userdata, id, control_ptr: *const pipewire::sys::pw_stream_control| {
-                if id == libspa_sys::SPA_PROP_channelVolumes {
-                    unsafe {
-                        let control = *control_ptr;
-                        if control.n_values > 0 {
-                            let values = std::slice::from_raw_parts(
-                                control.values,
-                                control.n_values as usize,
-                            );
-                            for v in values.iter() {
-                                if *v != DeadBeef::volume_get_amp() {
-                                    DeadBeef::volume_set_amp(*v);
-                                    break;
-                                }
-                            }
-                        }
-                    }
-                }
-            },
-        )
         .register();
 
-    let mut buffer: Vec<u8> = Vec::new();
-    let fmtpod = {
-        let fmt = init_fmt;
-        let format = db_format_to_pipewire(fmt);
-        let channels = fmt.channels as u32;
-        let rate = fmt.samplerate as u32;
-
-        create_audio_format_pod(format, channels, rate, &mut buffer)
-    };
-
-    if let Err(e) = stream.connect(
-        Direction::Output,
-        None,
-        StreamFlags::AUTOCONNECT | StreamFlags::MAP_BUFFERS | StreamFlags::RT_PROCESS,
-        &mut [&fmtpod],
-    ) {
-        DeadBeef::log_detailed(
-            DDB_LOG_LAYER_DEFAULT,
-            format!("Pipewire: Unable to connect stream, {}\n", e.to_string()).as_str(),
-        );
-        DeadBeef::sendmessage(DB_EV_STOP, 0, 0, 0);
-        return;
-    }
-
-    // When we receive a `Terminate` message, quit the main loop.
-    let _receiver = pw_receiver.attach(mainloop.as_ref(), {
+    // A hung daemon must not hang the caller with it
+    let timed_out = Rc::new(std::cell::Cell::new(false));
+    let timer = mainloop.loop_().add_timer({
         let mainloop = mainloop.clone();
-        move |msg| {
-            match msg {
-                PwThreadMessage::Terminate => {
-                    ourdisconnect.set(true);
-                    mainloop.quit();
-                }
-                PwThreadMessage::Pause => stream.set_active(false).unwrap(),
-                PwThreadMessage::Unpause => stream.set_active(true).unwrap(),
-                PwThreadMessage::SetFmt { format, state } => {
-                    ourdisconnect.set(true);
-                    if stream.disconnect().is_ok() {
-                        debug!("Set format called with: ");
-                        let pwfmt = db_format_to_pipewire(format);
-                        let channels = format.channels as u32;
-                        let samplerate = format.samplerate as u32;
-                        print_pipewire_format(pwfmt, channels, samplerate);
-
-                        let mut buffer: Vec<u8> = Vec::new();
-                        let newformatpod =
-                            create_audio_format_pod(pwfmt, channels, samplerate, &mut buffer);
-                        fmt.set(format);
-
-                        let mut flags = StreamFlags::AUTOCONNECT
-                            | StreamFlags::MAP_BUFFERS
-                            | StreamFlags::RT_PROCESS;
-
-                        if state != PlaybackState::Playing {
-                            flags |= StreamFlags::INACTIVE
-                        };
-
-                        if stream
-                            .connect(Direction::Output, None, flags, &mut [&newformatpod])
-                            .is_err()
-                        {
-                            DeadBeef::log_detailed(
-                                DDB_LOG_LAYER_DEFAULT,
-                                "Pipewire: Unable to connect stream, terminating\n",
-                            );
-                            DeadBeef::sendmessage(DB_EV_STOP, 0, 0, 0);
-                            return;
-                        }
-
-                        let rs = format!("1/{}", samplerate);
-                        let props = properties! {
-                            "node.rate" => rs,
-                            "node.latency" => "1200/48000",
-                        };
-                        update_stream_props(&stream, &props);
-                    }
-                }
-                PwThreadMessage::SetVol { newvol } => {
-                    let values = [newvol];
-                    stream
-                        .set_control(libspa_sys::SPA_PROP_channelVolumes, &values)
-                        .expect("Unable to set volume");
-                }
-                PwThreadMessage::SetTitle(title) => {
-                    let props = properties! {
-                        *pipewire::keys::MEDIA_NAME => title,
-                    };
-                    update_stream_props(&stream, &props);
-                }
-            };
+        let timed_out = timed_out.clone();
+        move |_| {
+            timed_out.set(true);
+            mainloop.quit();
         }
     });
-
-    mainloop.run();
+    timer
+        .update_timer(Some(ENUM_TIMEOUT), None)
+        .into_result()
+        .map_err(pipewire::Error::from)?;
+
+    while !done.get() {
+        if timed_out.get() {
+            return Err(PluginError::DaemonTimeout);
+        }
+        mainloop.run();
+    }
+    Ok(())
 }
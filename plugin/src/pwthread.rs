@@ -0,0 +1,1368 @@
+use crate::*;
+
+use std::collections::VecDeque;
+use std::rc::Rc;
+use std::sync::{mpsc, Arc};
+use std::time::{Duration, Instant};
+use std::{
+    cell::{Cell, RefCell},
+    thread,
+};
+
+use pipewire::{
+    context::Context,
+    core::{Core, PW_ID_CORE},
+    loop_::TimerSource,
+    main_loop::MainLoop,
+    properties::properties,
+    registry::Registry,
+    spa::utils::Direction,
+    stream::{Stream, StreamFlags, StreamListener, StreamRef, StreamState},
+};
+
+pub struct PlaybackThread {
+    handle: thread::JoinHandle<()>,
+    sender: pipewire::channel::Sender<PwThreadMessage>,
+    events: mpsc::Receiver<PwThreadEvent>,
+    stats: Arc<Stats>,
+}
+
+#[derive(Debug)]
+pub enum PwThreadMessage {
+    /// Quit once the stream has faded out, or with `drain` once it has played
+    /// out the audio held back for a crossfade.
+    Terminate {
+        drain: bool,
+    },
+    Pause,
+    Unpause,
+    SetFmt {
+        format: WaveFormat,
+        state: PlaybackState,
+    },
+    SetVol {
+        newvol: f32,
+    },
+    SetTitle(String),
+    /// The user is about to skip to another track or seek, dip the output.
+    SkipBegin {
+        seek: bool,
+    },
+    /// The new position is playing, bring the output back up.
+    SkipEnd,
+    /// Where playback is in the current track, so the thread can tell when it is
+    /// about to end and start holding audio back to crossfade with.
+    TrackPosition {
+        length: Option<Duration>,
+        position: Duration,
+    },
+    /// DeadBeef moved on to another track, crossfade to it if armed.
+    TrackChanged,
+    /// Sent by the process callback once a fade out has reached silence.
+    FadeDone,
+    /// Sent by a retired stream once it has played out its tail.
+    Retired(u32),
+    /// The connection to the PipeWire daemon is gone.
+    CoreLost,
+    /// Time to try connecting to the daemon again.
+    Reconnect,
+    /// New settings, along with what it takes to apply them.
+    Reconfigure {
+        config: Config,
+        change: ConfigChange,
+    },
+}
+
+/// Give up on a restarting daemon after this many attempts, roughly half a minute.
+const MAX_RECONNECT_ATTEMPTS: u32 = 10;
+
+/// Reported by the playback thread back to `OutputPlugin`, which decides what
+/// DeadBeef should be told.
+#[derive(Debug)]
+pub enum PwThreadEvent {
+    /// The primary stream is connected but not running.
+    Paused,
+    /// The primary stream is running.
+    Streaming,
+    /// The format PipeWire settled on for the primary stream.
+    Format { rate: u32, channels: u32 },
+    /// Time from the streamer to the speakers changed noticeably.
+    Latency(Duration),
+    /// The stream got linked to another sink, by `node.name`.
+    DeviceMoved(String),
+    /// The session manager corked the stream, or released it again.
+    Corked(bool),
+    /// The stream or the connection failed for good.
+    Failed(String),
+}
+
+/// Slack on top of the crossfade when deciding the track is about to end,
+/// covers the streamer switching tracks slightly off the reported length.
+const CROSSFADE_ARM_MARGIN: Duration = Duration::from_millis(500);
+
+/// Latency changes smaller than this aren't worth reporting.
+const LATENCY_THRESHOLD: Duration = Duration::from_millis(5);
+
+/// What to do once a fade out has reached silence.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum AfterFade {
+    Nothing,
+    Deactivate,
+    Quit,
+}
+
+/// Which half of a cross-rate track change has reached the thread. DeadBeef
+/// reports the song change and the new format in either order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TrackBoundary {
+    None,
+    /// The format change started the crossfade, the song change is covered.
+    FormatFirst,
+    /// The song change started the crossfade in the old format.
+    TrackFirst,
+}
+
+impl PlaybackThread {
+    pub fn new(init_fmt: WaveFormat, config: Config) -> Result<Self, PluginError> {
+        let (sender, receiver) = pipewire::channel::channel();
+        let (event_sender, events) = mpsc::channel();
+        let stats = Arc::new(Stats::default());
+        let thread_sender = sender.clone();
+        let thread_stats = stats.clone();
+        let handle = thread::Builder::new()
+            .name("pipewire".into())
+            .spawn(move || pw_thread_main(init_fmt, config, receiver, thread_sender, event_sender, thread_stats))?;
+        Ok(Self {
+            handle,
+            sender,
+            events,
+            stats,
+        })
+    }
+
+    pub fn stats(&self) -> Arc<Stats> {
+        self.stats.clone()
+    }
+
+    /// Block until the stream is connected or has failed.
+    /// Returns what else was reported in the meantime, for the caller to handle.
+    pub fn wait_ready(&self, timeout: Duration) -> Result<Vec<PwThreadEvent>, PluginError> {
+        let deadline = Instant::now() + timeout;
+        let mut events = Vec::new();
+        loop {
+            let left = deadline.saturating_duration_since(Instant::now());
+            match self.events.recv_timeout(left) {
+                Ok(PwThreadEvent::Paused | PwThreadEvent::Streaming) => return Ok(events),
+                Ok(PwThreadEvent::Failed(e)) => return Err(PluginError::StreamFailed(e)),
+                Ok(event) => events.push(event),
+                Err(mpsc::RecvTimeoutError::Timeout) => return Err(PluginError::Timeout),
+                // The thread gave up before creating a stream, it has logged why
+                Err(mpsc::RecvTimeoutError::Disconnected) => return Err(PluginError::ThreadGone),
+            }
+        }
+    }
+
+    /// Events the thread has reported since last time, without blocking.
+    pub fn events(&self) -> mpsc::TryIter<'_, PwThreadEvent> {
+        self.events.try_iter()
+    }
+
+    /// Wait at most `timeout` for the thread to quit. A stuck thread is left
+    /// running detached, there is no way to kill it.
+    pub fn join_timeout(self, timeout: Duration) -> Result<(), PluginError> {
+        let deadline = Instant::now() + timeout;
+        while !self.handle.is_finished() {
+            if Instant::now() >= deadline {
+                return Err(PluginError::ThreadStuck);
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        self.handle.join().map_err(|_| PluginError::ThreadPanicked)
+    }
+
+    pub fn msg(&self, msg: PwThreadMessage) -> Result<(), PluginError> {
+        self.sender.send(msg).map_err(|_| PluginError::ThreadGone)
+    }
+}
+
+/// Settings and state shared by every stream the playback thread owns.
+struct Shared {
+    /// Only used from the main loop, the process callback reads the copies below.
+    config: RefCell<Config>,
+    fade_ms: Cell<u32>,
+    crossfade_ms: Cell<u32>,
+    /// Whether we want the stream to be running, as opposed to the graph wanting it.
+    active: Cell<bool>,
+    /// Set while DeadBeef is paused because the session manager corked us.
+    corked: Cell<bool>,
+    /// Set while the session manager holds our volume down, going by `SPA_PROP_volume`.
+    ducked: Cell<bool>,
+    after_fade: Cell<AfterFade>,
+    /// How much of the current track the streamer has yet to hand over, unknown for streams.
+    track_left: Cell<Option<Duration>>,
+    /// Set from the core error callback, before the streams learn they are disconnected.
+    core_lost: Cell<bool>,
+    /// `media.name` for new streams, compiled once for the thread.
+    media_name: Option<TitleFormat>,
+    device_volume: DeviceVolume,
+    /// Lets the stream callbacks post messages back to the thread's main loop.
+    sender: pipewire::channel::Sender<PwThreadMessage>,
+    /// Reports back to `OutputPlugin`.
+    events: mpsc::Sender<PwThreadEvent>,
+    stats: Arc<Stats>,
+}
+
+impl Shared {
+    fn new(
+        config: Config,
+        sender: pipewire::channel::Sender<PwThreadMessage>,
+        events: mpsc::Sender<PwThreadEvent>,
+        stats: Arc<Stats>,
+    ) -> Self {
+        Self {
+            fade_ms: Cell::new(config.fade_ms),
+            crossfade_ms: Cell::new(config.crossfade_ms),
+            device_volume: DeviceVolume::new(&config.soundcard),
+            config: RefCell::new(config),
+            active: Cell::new(true),
+            corked: Cell::new(false),
+            ducked: Cell::new(false),
+            after_fade: Cell::new(AfterFade::Nothing),
+            track_left: Cell::new(None),
+            core_lost: Cell::new(false),
+            media_name: TitleFormat::compile(MEDIA_NAME_FORMAT)
+                .inspect_err(|e| debug!("Unable to compile media name format: {e}"))
+                .ok(),
+            sender,
+            events,
+            stats,
+        }
+    }
+
+    fn set_config(&self, config: Config) {
+        self.fade_ms.set(config.fade_ms);
+        self.crossfade_ms.set(config.crossfade_ms);
+        self.device_volume.set_target(&config.soundcard);
+        self.config.replace(config);
+    }
+
+    fn policy(&self) -> PolicyAction {
+        self.config.borrow().policy
+    }
+
+    fn report(&self, event: PwThreadEvent) {
+        // The plugin may already have dropped its end while stopping
+        let _ = self.events.send(event);
+    }
+
+    fn media_name(&self) -> Option<String> {
+        let track = DeadBeef::current_track().ok()?;
+        self.media_name.as_ref()?.eval(&track).ok()
+    }
+
+    fn set_track_position(&self, length: Option<Duration>, position: Duration) {
+        self.track_left.set(length.map(|length| length.saturating_sub(position)));
+    }
+
+    /// Whether the track ends soon enough to start holding audio back for a crossfade.
+    /// The hold back fills at most one extra buffer per cycle, so it takes twice
+    /// the crossfade of the track to build up.
+    fn track_ending(&self) -> bool {
+        let crossfade = Duration::from_millis(self.crossfade_ms.get() as u64);
+        !crossfade.is_zero()
+            && self
+                .track_left
+                .get()
+                .is_some_and(|left| left <= crossfade * 2 + CROSSFADE_ARM_MARGIN)
+    }
+
+    fn fade_frames(&self, fmt: WaveFormat) -> u32 {
+        fmt.duration_to_frames(Duration::from_millis(self.fade_ms.get() as u64)) as u32
+    }
+
+    fn crossfade_frames(&self, fmt: WaveFormat) -> u32 {
+        fmt.duration_to_frames(Duration::from_millis(self.crossfade_ms.get() as u64)) as u32
+    }
+}
+
+/// State of a single stream, shared with its callbacks.
+struct StreamData {
+    serial: u32,
+    fmt: Cell<WaveFormat>,
+    ramp: RefCell<Ramp>,
+    matrix: RefCell<ChannelMatrix>,
+    ourdisconnect: Cell<bool>,
+    /// A retired stream no longer reads from the streamer and only plays out `tail`.
+    retired: Cell<bool>,
+    /// Audio read ahead of playback when crossfading, so there is something left
+    /// to fade out once DeadBeef moves on to the next track.
+    tail: RefCell<VecDeque<u8>>,
+    /// Set near the end of a track, from then on `tail` is kept topped up.
+    armed: Cell<bool>,
+    /// Last latency reported to the plugin.
+    latency: Cell<Duration>,
+}
+
+/// A playback stream fed from the DeadBeef streamer, in its own format.
+struct OutputStream {
+    // Declared before `stream` so it is unregistered before the stream is destroyed
+    _listener: StreamListener<()>,
+    stream: Stream,
+    data: Rc<StreamData>,
+}
+
+impl OutputStream {
+    fn new(
+        core: &Core,
+        serial: u32,
+        format: WaveFormat,
+        active: bool,
+        shared: &Rc<Shared>,
+    ) -> Result<Self, PluginError> {
+        let device = shared.config.borrow().soundcard.clone();
+
+        let mut props = properties! {
+            *pipewire::keys::MEDIA_TYPE => "Audio",
+            *pipewire::keys::MEDIA_CATEGORY => "Playback",
+            *pipewire::keys::MEDIA_ROLE => "Music",
+            *pipewire::keys::NODE_NAME => "DeadBeef",
+            *pipewire::keys::APP_NAME => "DeadBeef",
+            *pipewire::keys::APP_ID => "music.player.deadbeef",
+            *pipewire::keys::APP_ICON_NAME => "deadbeef",
+            "node.latency" => "1200/48000",
+        };
+
+        let s = format!("1/{}", format.samplerate());
+        props.insert("node.rate", s);
+
+        if !device.eq("default") {
+            props.insert(*pipewire::keys::TARGET_OBJECT, device);
+        }
+
+        if let Some(media_name) = shared.media_name() {
+            props.insert(*pipewire::keys::MEDIA_NAME, media_name);
+        }
+
+        let stream = Stream::new(core, "deadbeef", props)?;
+
+        let data = Rc::new(StreamData {
+            serial,
+            fmt: Cell::new(format),
+            ramp: RefCell::new(Ramp::new()),
+            matrix: RefCell::new(ChannelMatrix::identity(format.channels() as usize)),
+            ourdisconnect: Cell::new(false),
+            retired: Cell::new(false),
+            tail: RefCell::new(VecDeque::new()),
+            armed: Cell::new(false),
+            latency: Cell::new(Duration::ZERO),
+        });
+
+        let listener = stream
+            .add_local_listener::<()>()
+            .state_changed({
+                let data = data.clone();
+                let shared = shared.clone();
+                move |stream, _userdata, old, new| {
+                    debug!("State changed: {old:?} -> {new:?}");
+                    // Whatever happens to a stream we are retiring doesn't matter,
+                    // and a lost core is handled by reconnecting.
+                    if data.retired.get() || shared.core_lost.get() {
+                        return;
+                    }
+                    if matches!(new, StreamState::Paused | StreamState::Streaming) {
+                        shared.stats.set_node_id(stream.node_id());
+                        if let Some(sink) = shared.device_volume.set_node_id(stream.node_id()) {
+                            shared.report(PwThreadEvent::DeviceMoved(sink));
+                        }
+                    }
+                    match new {
+                        StreamState::Error(x) => {
+                            let msg = format!("Pipewire playback error: {x}");
+                            DeadBeef::log_detailed(DDB_LOG_LAYER_DEFAULT, &msg);
+                            shared.report(PwThreadEvent::Failed(x));
+                        }
+                        StreamState::Unconnected => {
+                            if !data.ourdisconnect.get() {
+                                DeadBeef::log_detailed(DDB_LOG_LAYER_DEFAULT, "Pipewire disconnected.");
+                                shared.report(PwThreadEvent::Failed("Disconnected".into()));
+                            }
+                        }
+                        StreamState::Connecting => {
+                            data.ourdisconnect.set(false);
+                        }
+                        StreamState::Paused => {
+                            // Going from streaming to paused without us asking for it means
+                            // the node got suspended or corked by the session manager.
+                            let corked = old == StreamState::Streaming
+                                && shared.active.get()
+                                && !data.ourdisconnect.get();
+                            if corked && shared.policy() != PolicyAction::Pause {
+                                return;
+                            }
+                            shared.report(PwThreadEvent::Paused);
+                            if corked && !shared.corked.get() {
+                                DeadBeef::log_detailed(
+                                    DDB_LOG_LAYER_INFO,
+                                    "Pipewire: Stream corked by session manager, pausing.\n",
+                                );
+                                shared.corked.set(true);
+                                shared.report(PwThreadEvent::Corked(true));
+                            }
+                        }
+                        StreamState::Streaming => {
+                            shared.report(PwThreadEvent::Streaming);
+                            if shared.corked.replace(false) {
+                                DeadBeef::log_detailed(
+                                    DDB_LOG_LAYER_INFO,
+                                    "Pipewire: Stream released by session manager, resuming.\n",
+                                );
+                                shared.report(PwThreadEvent::Corked(false));
+                            }
+                        }
+                    }
+                }
+            })
+            .process({
+                let data = data.clone();
+                let shared = shared.clone();
+                let mut scratch: Vec<u8> = Vec::new();
+                let mut held_frames = 0;
+                move |stream, _userdata| {
+                    let started = Instant::now();
+                    process(stream, &data, &shared, &mut scratch, &mut held_frames);
+                    shared.stats.cycle(started.elapsed());
+                }
+            })
+            .param_changed({
+                let data = data.clone();
+                let shared = shared.clone();
+                move |_stream, _userdata, id, param| {
+                    let Some(param) = param else {
+                        return;
+                    };
+                    if id != libspa_sys::SPA_PARAM_Format || data.retired.get() {
+                        return;
+                    }
+                    let mut info = pipewire::spa::param::audio::AudioInfoRaw::new();
+                    if info.parse(param).is_ok() {
+                        shared.report(PwThreadEvent::Format {
+                            rate: info.rate(),
+                            channels: info.channels(),
+                        });
+                    }
+                }
+            })
+            .control_info({
+                let data = data.clone();
+                let shared = shared.clone();
+                move |_stream, _userdata, id, control_ptr: *const pipewire::sys::pw_stream_control| {
+                    if data.retired.get() {
+                        return;
+                    }
+                    let values = unsafe {
+                        let control = *control_ptr;
+                        if control.n_values == 0 || control.values.is_null() {
+                            return;
+                        }
+                        std::slice::from_raw_parts(control.values, control.n_values as usize)
+                    };
+                    if id == libspa_sys::SPA_PROP_volume {
+                        shared.ducked.set(values[0] < 1.0);
+                        return;
+                    }
+                    // Whatever the policy does while corking or ducking us is
+                    // undone afterwards, it is not the user's volume. With Ignore
+                    // every change is taken as the user's.
+                    let held = match shared.policy() {
+                        PolicyAction::Pause => shared.corked.get() || shared.ducked.get(),
+                        PolicyAction::Duck => shared.ducked.get(),
+                        PolicyAction::Ignore => false,
+                    };
+                    if id == libspa_sys::SPA_PROP_channelVolumes && !held {
+                        if let Some(v) = values.iter().find(|v| **v != DeadBeef::volume_get_amp()) {
+                            DeadBeef::volume_set_amp(*v);
+                        }
+                    }
+                }
+            })
+            .register()?;
+
+        let output = Self {
+            _listener: listener,
+            stream,
+            data,
+        };
+        output.connect(format, active, shared)?;
+        Ok(output)
+    }
+
+    fn connect(&self, format: WaveFormat, active: bool, shared: &Shared) -> Result<(), PluginError> {
+        let pwfmt = db_format_to_pipewire(format);
+        let channels = format.channels();
+        let samplerate = format.samplerate();
+        print_pipewire_format(pwfmt, channels, samplerate);
+
+        let mut buffer: Vec<u8> = Vec::new();
+        let formatpod = create_audio_format_pod(pwfmt, channels, samplerate, &mut buffer)?;
+        self.data.fmt.set(format);
+
+        self.load_matrix(shared);
+
+        let mut flags = StreamFlags::AUTOCONNECT | StreamFlags::MAP_BUFFERS | StreamFlags::RT_PROCESS;
+
+        if !active {
+            flags |= StreamFlags::INACTIVE
+        };
+
+        self.stream
+            .connect(Direction::Output, None, flags, &mut [formatpod])
+    }
+
+    /// Parse the configured channel matrix for the stream's current format.
+    fn load_matrix(&self, shared: &Shared) {
+        let channels = self.data.fmt.get().channels();
+        let matrix = ChannelMatrix::parse(&shared.config.borrow().channel_matrix, channels)
+            .unwrap_or_else(|e| {
+                DeadBeef::log_detailed(
+                    DDB_LOG_LAYER_DEFAULT,
+                    format!("Pipewire: Ignoring channel remapping, {e}\n").as_str(),
+                );
+                ChannelMatrix::identity(channels as usize)
+            });
+        self.data.matrix.replace(matrix);
+    }
+
+    /// Reconnect the stream with a new format.
+    fn set_format(&self, format: WaveFormat, active: bool, shared: &Shared) -> Result<(), PluginError> {
+        self.data.ourdisconnect.set(true);
+        self.stream.disconnect()?;
+        debug!("Set format called with: ");
+        self.connect(format, active, shared)?;
+
+        let rs = format!("1/{}", format.samplerate());
+        let props = properties! {
+            "node.rate" => rs,
+            "node.latency" => "1200/48000",
+        };
+        update_stream_props(&self.stream, &props);
+        Ok(())
+    }
+
+    /// Stop reading from the streamer and fade out what is left of the tail over at most `frames`.
+    fn retire(&self, frames: u32) {
+        let fmt = self.data.fmt.get();
+        let tail_frames = fmt.bytes_to_frames(self.data.tail.borrow().len()) as u32;
+
+        self.data.retired.set(true);
+        self.data.ramp.borrow_mut().fade_out(frames.min(tail_frames));
+    }
+
+    /// Throw away audio held back for a crossfade that is no longer coming.
+    fn drop_tail(&self) {
+        self.data.armed.set(false);
+        self.data.tail.borrow_mut().clear();
+    }
+
+    fn is_streaming(&self) -> bool {
+        self.stream.state() == StreamState::Streaming
+    }
+}
+
+fn process(
+    stream: &StreamRef,
+    data: &StreamData,
+    shared: &Shared,
+    scratch: &mut Vec<u8>,
+    held_frames: &mut i32,
+) {
+    let fmt = data.fmt.get();
+
+    // This prevents glitches during format changes
+    if data.ourdisconnect.get() {
+        shared.stats.skipped_cycle();
+        return;
+    }
+
+    if !data.retired.get() {
+        check_latency(stream, data, shared);
+    }
+
+    match stream.dequeue_buffer() {
+        None => shared.stats.no_buffer(),
+        Some(mut buffer) => {
+            let req = buffer.requested();
+            if req > 0 {
+                shared.stats.set_quantum(req as u32);
+            }
+            let datas = buffer.datas_mut();
+
+            let maxsize = datas[0].as_raw().maxsize as usize;
+            if let Some(d) = datas[0].data() {
+                let len = if req > 0 {
+                    fmt.frames_to_bytes(req as usize)
+                } else {
+                    fmt.duration_to_bytes(Duration::from_millis(25)).min(fmt.align_bytes(maxsize))
+                };
+                let end = fmt.align_bytes(len.min(d.len()));
+
+                let bytesread = if data.retired.get() {
+                    pop_tail(&mut d[..end], &mut data.tail.borrow_mut())
+                } else if shared.corked.get() || data.ramp.borrow().is_silent() {
+                    // Play silence while corked, DeadBeef may not have paused yet.
+                    0
+                } else if data.armed.get() || !data.tail.borrow().is_empty() {
+                    // Once disarmed the tail plays out and reads catch up with the streamer
+                    let delay = if data.armed.get() {
+                        fmt.frames_to_bytes(shared.crossfade_frames(fmt) as usize)
+                    } else {
+                        0
+                    };
+                    read_delayed(&mut d[..end], &mut data.tail.borrow_mut(), delay, scratch, fmt, shared)
+                } else if DeadBeef::streamer_has_data() {
+                    read_streamer(&mut d[..end], fmt, shared)
+                } else {
+                    // Silence on the way to a pause or stop is expected
+                    if shared.active.get() && !data.ramp.borrow().is_fading_out() {
+                        shared.stats.underrun();
+                    }
+                    0
+                };
+
+                if bytesread > 0 {
+                    data.matrix.borrow().apply(&mut d[..(bytesread as usize).min(end)], &fmt);
+                }
+
+                let filled = bytesread.max(0) as usize;
+                if filled < len {
+                    d[filled..].fill(0);
+                }
+
+                let finished = data.ramp.borrow_mut().apply(&mut d[..end], &fmt);
+
+                if data.retired.get() {
+                    if data.tail.borrow().is_empty() || data.ramp.borrow().is_silent() {
+                        data.ourdisconnect.set(true);
+                        let _ = shared.sender.send(PwThreadMessage::Retired(data.serial));
+                    }
+                } else {
+                    if finished && data.ramp.borrow().is_silent() {
+                        let _ = shared.sender.send(PwThreadMessage::FadeDone);
+                    }
+
+                    if !data.armed.get() && shared.track_ending() {
+                        data.armed.set(true);
+                    }
+
+                    // Don't stay dipped forever if the skip never completes
+                    if data.ramp.borrow().is_silent() && shared.after_fade.get() == AfterFade::Nothing {
+                        *held_frames += fmt.bytes_to_frames(end) as i32;
+                        if *held_frames > fmt.samplerate() as i32 {
+                            *held_frames = 0;
+                            data.ramp.borrow_mut().fade_in(shared.fade_frames(fmt));
+                        }
+                    } else {
+                        *held_frames = 0;
+                    }
+                }
+
+                *datas[0].chunk_mut().size_mut() = bytesread as u32;
+                *datas[0].chunk_mut().offset_mut() = 0;
+                *datas[0].chunk_mut().stride_mut() = fmt.bytes_per_frame() as i32;
+            }
+        }
+    };
+}
+
+/// Let the plugin know when the stream's latency has moved.
+fn check_latency(stream: &StreamRef, data: &StreamData, shared: &Shared) {
+    let mut time: pipewire::sys::pw_time = unsafe { std::mem::zeroed() };
+    if unsafe { pipewire::sys::pw_stream_get_time(stream.as_raw_ptr(), &mut time) } < 0
+        || time.rate.denom == 0
+    {
+        return;
+    }
+    shared.stats.set_rate(time.rate.denom);
+    let nanos = time.delay.max(0) as u128 * time.rate.num as u128 * 1_000_000_000 / time.rate.denom as u128;
+    let latency = Duration::from_nanos(nanos as u64);
+    let last = data.latency.get();
+    let change = if latency > last { latency - last } else { last - latency };
+    if change >= LATENCY_THRESHOLD {
+        data.latency.set(latency);
+        shared.report(PwThreadEvent::Latency(latency));
+    }
+}
+
+/// Read from the streamer through `tail`, keeping `delay` bytes queued up.
+/// The queue is filled by reading at most one extra buffer's worth per cycle.
+fn read_delayed(
+    out: &mut [u8],
+    tail: &mut VecDeque<u8>,
+    delay: usize,
+    scratch: &mut Vec<u8>,
+    fmt: WaveFormat,
+    shared: &Shared,
+) -> i32 {
+    let want = out.len() + delay.saturating_sub(tail.len()).min(out.len());
+
+    if DeadBeef::streamer_has_data() {
+        scratch.resize(want, 0);
+        let bytesread = streamer_read(scratch, fmt, shared);
+        if bytesread > 0 {
+            tail.extend(&scratch[..bytesread as usize]);
+        }
+    }
+
+    // Only what goes out counts, the streamer may well not have the extra
+    let n = pop_tail(out, tail);
+    count_read(n, out.len(), &shared.stats);
+    n
+}
+
+/// Read from the streamer, counting reads that come up short.
+fn read_streamer(buf: &mut [u8], fmt: WaveFormat, shared: &Shared) -> i32 {
+    let bytesread = streamer_read(buf, fmt, shared);
+    count_read(bytesread, buf.len(), &shared.stats);
+    bytesread
+}
+
+/// Read from the streamer, keeping track of how much of the track is left.
+fn streamer_read(buf: &mut [u8], fmt: WaveFormat, shared: &Shared) -> i32 {
+    let bytesread = DeadBeef::streamer_read(buf);
+    if bytesread > 0 {
+        let read = fmt.bytes_to_duration(bytesread as usize);
+        shared.track_left.set(shared.track_left.get().map(|left| left.saturating_sub(read)));
+    }
+    bytesread
+}
+
+fn count_read(bytesread: i32, wanted: usize, stats: &Stats) {
+    if bytesread <= 0 {
+        stats.underrun();
+    } else if (bytesread as usize) < wanted {
+        stats.short_read();
+    }
+}
+
+fn pop_tail(out: &mut [u8], tail: &mut VecDeque<u8>) -> i32 {
+    let n = out.len().min(tail.len());
+    for (o, b) in out.iter_mut().zip(tail.drain(..n)) {
+        *o = b;
+    }
+    n as i32
+}
+
+/// Start a fade out, returns false if the stream isn't running and there is nothing to fade.
+fn start_fade_out(output: &OutputStream, frames: u32) -> bool {
+    if frames == 0 || !output.is_streaming() {
+        output.data.ramp.borrow_mut().fade_out(0);
+        return false;
+    }
+    output.data.ramp.borrow_mut().fade_out(frames);
+    true
+}
+
+pub fn make_channel_map(channels: u32) -> [u32; 64] {
+    let mut position = [0; 64];
+    if channels == 1 {
+        position[0] = libspa_sys::SPA_AUDIO_CHANNEL_MONO;
+    }
+    if channels >= 2 {
+        position[0] = libspa_sys::SPA_AUDIO_CHANNEL_FL;
+        position[1] = libspa_sys::SPA_AUDIO_CHANNEL_FR;
+    }
+    if channels >= 3 {
+        position[2] = libspa_sys::SPA_AUDIO_CHANNEL_FC;
+    }
+    if channels >= 4 {
+        position[3] = libspa_sys::SPA_AUDIO_CHANNEL_LFE;
+    }
+    if channels >= 6 {
+        position[4] = libspa_sys::SPA_AUDIO_CHANNEL_RL;
+        position[5] = libspa_sys::SPA_AUDIO_CHANNEL_RR;
+    }
+    if channels >= 8 {
+        position[6] = libspa_sys::SPA_AUDIO_CHANNEL_FLC;
+        position[7] = libspa_sys::SPA_AUDIO_CHANNEL_FRC;
+    }
+    if channels >= 9 {
+        position[8] = libspa_sys::SPA_AUDIO_CHANNEL_RC;
+    }
+    if channels >= 11 {
+        position[9] = libspa_sys::SPA_AUDIO_CHANNEL_SL;
+        position[10] = libspa_sys::SPA_AUDIO_CHANNEL_SR;
+    }
+    if channels >= 12 {
+        position[11] = libspa_sys::SPA_AUDIO_CHANNEL_TC;
+    }
+    if channels >= 15 {
+        position[12] = libspa_sys::SPA_AUDIO_CHANNEL_TFL;
+        position[13] = libspa_sys::SPA_AUDIO_CHANNEL_TFC;
+        position[14] = libspa_sys::SPA_AUDIO_CHANNEL_TFR;
+    }
+    if channels >= 18 {
+        position[15] = libspa_sys::SPA_AUDIO_CHANNEL_TRL;
+        position[16] = libspa_sys::SPA_AUDIO_CHANNEL_TRC;
+        position[17] = libspa_sys::SPA_AUDIO_CHANNEL_TRR;
+    }
+
+    position
+}
+
+fn create_audio_format_pod(
+    format: pipewire::spa::param::audio::AudioFormat,
+    channels: u32,
+    rate: u32,
+    buffer: &mut Vec<u8>,
+) -> Result<&pipewire::spa::pod::Pod, PluginError> {
+    let mut audio_info = pipewire::spa::param::audio::AudioInfoRaw::new();
+    audio_info.set_format(format);
+    audio_info.set_rate(rate);
+    audio_info.set_channels(channels);
+
+    audio_info.set_position(make_channel_map(channels));
+
+    let values = pipewire::spa::pod::serialize::PodSerializer::serialize(
+        std::io::Cursor::new(buffer),
+        &pipewire::spa::pod::Value::Object(pipewire::spa::pod::Object {
+            type_: libspa_sys::SPA_TYPE_OBJECT_Format,
+            id: libspa_sys::SPA_PARAM_EnumFormat,
+            properties: audio_info.into(),
+        }),
+    )
+    .map_err(|_| PluginError::FormatPod)?
+    .0
+    .into_inner();
+
+    pipewire::spa::pod::Pod::from_bytes(values).ok_or(PluginError::FormatPod)
+}
+
+/// Connection to the daemon along with the registry we watch on it.
+/// Replaced as a whole when the daemon restarts.
+struct Session {
+    // Field order is drop order, everything goes before the core
+    _registry_listener: pipewire::registry::Listener,
+    _registry: Registry,
+    _core_listener: pipewire::core::Listener,
+    core: Core,
+}
+
+impl Session {
+    fn connect(context: &Context, shared: &Rc<Shared>) -> Result<Self, PluginError> {
+        let client_props = properties! {
+            *pipewire::keys::APP_NAME => "DeadBeef",
+            *pipewire::keys::APP_ID => "music.player.deadbeef",
+            *pipewire::keys::APP_ICON_NAME => "deadbeef"
+        };
+        let core = context.connect(Some(client_props))?;
+
+        // Registered before any stream, so this runs before they go unconnected
+        let core_listener = core
+            .add_listener_local()
+            .error({
+                let shared = shared.clone();
+                move |id, _seq, res, message| {
+                    let broken_pipe = std::io::Error::from_raw_os_error(-res).kind()
+                        == std::io::ErrorKind::BrokenPipe;
+                    if id == PW_ID_CORE && broken_pipe && !shared.core_lost.replace(true) {
+                        debug!("Core error: {message}");
+                        let _ = shared.sender.send(PwThreadMessage::CoreLost);
+                    }
+                }
+            })
+            .register();
+
+        // Watch the graph to find out which sink we end up linked to
+        let registry = core.get_registry()?;
+        let registry_listener = registry
+            .add_listener_local()
+            .global({
+                let shared = shared.clone();
+                move |global| {
+                    if let Some(sink) = shared.device_volume.global(global) {
+                        shared.report(PwThreadEvent::DeviceMoved(sink));
+                    }
+                }
+            })
+            .global_remove({
+                let shared = shared.clone();
+                move |id| shared.device_volume.global_remove(id)
+            })
+            .register();
+
+        Ok(Self {
+            _registry_listener: registry_listener,
+            _registry: registry,
+            _core_listener: core_listener,
+            core,
+        })
+    }
+}
+
+fn pw_thread_main(
+    init_fmt: WaveFormat,
+    config: Config,
+    pw_receiver: pipewire::channel::Receiver<PwThreadMessage>,
+    sender: pipewire::channel::Sender<PwThreadMessage>,
+    events: mpsc::Sender<PwThreadEvent>,
+    stats: Arc<Stats>,
+) {
+    let fail = |context: &str, e: PluginError| {
+        e.log(context);
+        let _ = events.send(PwThreadEvent::Failed(e.to_string()));
+    };
+
+    let mainloop = match MainLoop::new(None) {
+        Ok(mainloop) => mainloop,
+        Err(e) => return fail("Unable to create main loop", e.into()),
+    };
+    let context = match Context::new(&mainloop) {
+        Ok(context) => context,
+        Err(e) => return fail("Unable to create context", e.into()),
+    };
+
+    let shared = Rc::new(Shared::new(config, sender, events.clone(), stats));
+
+    let session = match Session::connect(&context, &shared) {
+        Ok(session) => session,
+        Err(e) => return fail("Unable to connect to daemon", e),
+    };
+
+    let primary = match OutputStream::new(&session.core, 0, init_fmt, true, &shared) {
+        Ok(a) => a,
+        Err(e) => return fail("Unable to create stream", e),
+    };
+
+    let reconnect_timer = ReconnectTimer::new(&mainloop, shared.sender.clone());
+
+    let thread = PwThread {
+        shared,
+        reconnect_timer,
+        mainloop: mainloop.clone(),
+        primary: RefCell::new(primary),
+        retiring: RefCell::new(None),
+        serial: Cell::new(0),
+        boundary: Cell::new(TrackBoundary::None),
+        session: RefCell::new(session),
+        context,
+        reconnect_attempts: Cell::new(0),
+    };
+
+    let _receiver = pw_receiver.attach(mainloop.as_ref(), move |msg| thread.handle(msg));
+
+    mainloop.run();
+}
+
+/// Posts `Reconnect` once it fires, armed while the daemon is gone.
+struct ReconnectTimer {
+    // Declared before `_mainloop` so it is destroyed while the loop is still alive
+    timer: TimerSource<'static>,
+    _mainloop: MainLoop,
+}
+
+impl ReconnectTimer {
+    fn new(mainloop: &MainLoop, sender: pipewire::channel::Sender<PwThreadMessage>) -> Self {
+        let timer = mainloop.loop_().add_timer(move |_| {
+            let _ = sender.send(PwThreadMessage::Reconnect);
+        });
+        // SAFETY: The timer only borrows the loop, which the `MainLoop` handle stored
+        // next to it keeps alive. Fields are dropped in declaration order, so the timer
+        // goes first, and it never leaves the struct.
+        let timer = unsafe { std::mem::transmute::<TimerSource<'_>, TimerSource<'static>>(timer) };
+        Self {
+            timer,
+            _mainloop: mainloop.clone(),
+        }
+    }
+}
+
+/// State of the playback thread, owned by the message handler.
+struct PwThread {
+    shared: Rc<Shared>,
+    reconnect_timer: ReconnectTimer,
+    mainloop: MainLoop,
+    /// The stream fed from the streamer.
+    primary: RefCell<OutputStream>,
+    /// The previous stream while it fades out after a crossfade.
+    retiring: RefCell<Option<OutputStream>>,
+    serial: Cell<u32>,
+    /// Pairs up the song change and format change of a crossfade, so it only happens once.
+    boundary: Cell<TrackBoundary>,
+    // Declared after the streams so they are dropped before the session
+    session: RefCell<Session>,
+    context: Context,
+    reconnect_attempts: Cell<u32>,
+}
+
+impl PwThread {
+    fn handle(&self, msg: PwThreadMessage) {
+        let shared = &self.shared;
+        if shared.core_lost.get() {
+            self.handle_disconnected(msg);
+            return;
+        }
+        match msg {
+            // When we receive a `Terminate` message, quit the main loop.
+            PwThreadMessage::Terminate { drain } => {
+                self.boundary.set(TrackBoundary::None);
+                let primary = self.primary.borrow();
+                // Nothing follows the last track, so the tail is all that is left of it
+                if drain && primary.is_streaming() && !primary.data.tail.borrow().is_empty() {
+                    primary.data.armed.set(false);
+                    primary.data.retired.set(true);
+                    shared.after_fade.set(AfterFade::Quit);
+                    return;
+                }
+                primary.drop_tail();
+                if start_fade_out(&primary, shared.fade_frames(primary.data.fmt.get())) {
+                    shared.after_fade.set(AfterFade::Quit);
+                } else {
+                    primary.data.ourdisconnect.set(true);
+                    self.mainloop.quit();
+                }
+            }
+            PwThreadMessage::Pause => {
+                // Cut a crossfade short rather than dropping the stream mid ramp
+                let stopped = self.retiring.borrow().as_ref().is_some_and(|old| {
+                    !start_fade_out(old, shared.fade_frames(old.data.fmt.get()))
+                });
+                if stopped {
+                    self.retiring.take();
+                }
+                // Keep a corked stream active so we notice when the policy releases it
+                if !shared.corked.get() {
+                    shared.active.set(false);
+                    let primary = self.primary.borrow();
+                    if start_fade_out(&primary, shared.fade_frames(primary.data.fmt.get())) {
+                        shared.after_fade.set(AfterFade::Deactivate);
+                    } else {
+                        self.set_active(&primary, false);
+                    }
+                }
+            }
+            PwThreadMessage::Unpause => {
+                shared.corked.set(false);
+                shared.active.set(true);
+                shared.after_fade.set(AfterFade::Nothing);
+                let primary = self.primary.borrow();
+                let frames = shared.fade_frames(primary.data.fmt.get());
+                primary.data.ramp.borrow_mut().fade_in(frames);
+                self.set_active(&primary, true);
+            }
+            PwThreadMessage::SetFmt { format, state } => {
+                shared.stats.format_changed();
+                let playing = state == PlaybackState::Playing;
+                shared.active.set(playing);
+                let (armed, fading_in) = {
+                    let primary = self.primary.borrow();
+                    let ramp = primary.data.ramp.borrow();
+                    (
+                        primary.data.armed.get() && primary.is_streaming(),
+                        ramp.is_running() && !ramp.is_fading_out(),
+                    )
+                };
+                let boundary = self.boundary.replace(TrackBoundary::None);
+                if boundary == TrackBoundary::TrackFirst && playing && fading_in {
+                    // The streamer holds the new track back until the format is set,
+                    // so the incoming stream has been fading in over nothing
+                    self.restart_incoming(format);
+                } else if shared.crossfade_ms.get() > 0 && playing && armed {
+                    self.boundary.set(TrackBoundary::FormatFirst);
+                    self.crossfade(format);
+                } else if let Err(e) = self.primary.borrow().set_format(format, playing, shared) {
+                    e.log("Unable to connect stream, stopping");
+                    shared.report(PwThreadEvent::Failed(e.to_string()));
+                }
+            }
+            PwThreadMessage::SetVol { newvol } => {
+                shared.device_volume.save(newvol);
+                let values = [newvol];
+                let primary = self.primary.borrow();
+                let retiring = self.retiring.borrow();
+                for output in std::iter::once(&*primary).chain(retiring.as_ref()) {
+                    // Not worth stopping playback over, the next change may get through
+                    if let Err(e) = output.stream.set_control(libspa_sys::SPA_PROP_channelVolumes, &values) {
+                        PluginError::from(e).log("Unable to set volume");
+                    }
+                }
+            }
+            PwThreadMessage::SetTitle(title) => {
+                let props = properties! {
+                    *pipewire::keys::MEDIA_NAME => title,
+                };
+                update_stream_props(&self.primary.borrow().stream, &props);
+            }
+            PwThreadMessage::SkipBegin { seek } => {
+                let primary = self.primary.borrow();
+                // Track skips near the end are covered by the crossfade
+                if (seek || !primary.data.armed.get()) && shared.after_fade.get() == AfterFade::Nothing {
+                    start_fade_out(&primary, shared.fade_frames(primary.data.fmt.get()));
+                }
+                self.boundary.set(TrackBoundary::None);
+            }
+            PwThreadMessage::SkipEnd => {
+                self.boundary.set(TrackBoundary::None);
+                self.skip_end();
+            }
+            PwThreadMessage::TrackPosition { length, position } => shared.set_track_position(length, position),
+            PwThreadMessage::TrackChanged => {
+                if self.boundary.replace(TrackBoundary::None) == TrackBoundary::FormatFirst {
+                    return;
+                }
+                let armed = self.primary.borrow().data.armed.get() && self.primary.borrow().is_streaming();
+                if shared.crossfade_ms.get() > 0 && shared.active.get() && armed {
+                    // A format change may still follow, see `SetFmt`
+                    self.boundary.set(TrackBoundary::TrackFirst);
+                    let format = self.primary.borrow().data.fmt.get();
+                    self.crossfade(format);
+                } else {
+                    self.skip_end();
+                }
+            }
+            PwThreadMessage::FadeDone => match shared.after_fade.replace(AfterFade::Nothing) {
+                AfterFade::Deactivate => self.set_active(&self.primary.borrow(), false),
+                AfterFade::Quit => {
+                    self.primary.borrow().data.ourdisconnect.set(true);
+                    self.mainloop.quit();
+                }
+                AfterFade::Nothing => {}
+            },
+            PwThreadMessage::Retired(serial) => {
+                // The primary only retires when draining before quitting
+                if self.primary.borrow().data.serial == serial {
+                    if shared.after_fade.get() == AfterFade::Quit {
+                        self.mainloop.quit();
+                    }
+                    return;
+                }
+                let done = self
+                    .retiring
+                    .borrow()
+                    .as_ref()
+                    .is_some_and(|r| r.data.serial == serial);
+                if done {
+                    self.retiring.take();
+                }
+            }
+            PwThreadMessage::Reconfigure { config, change } => {
+                shared.set_config(config);
+                match change {
+                    ConfigChange::None => {}
+                    ConfigChange::Update => self.primary.borrow().load_matrix(shared),
+                    ConfigChange::Retarget => self.retarget(),
+                    ConfigChange::Rebuild => self.rebuild(),
+                }
+            }
+            // Only meaningful while disconnected
+            PwThreadMessage::CoreLost | PwThreadMessage::Reconnect => {}
+        };
+    }
+
+    /// Keep track of what DeadBeef wants while there is no daemon to talk to,
+    /// so the stream comes back the way it should be.
+    fn handle_disconnected(&self, msg: PwThreadMessage) {
+        let shared = &self.shared;
+        match msg {
+            PwThreadMessage::CoreLost => {
+                DeadBeef::log_detailed(
+                    DDB_LOG_LAYER_DEFAULT,
+                    "Pipewire: Lost connection to the daemon, reconnecting.\n",
+                );
+                self.retiring.take();
+                shared.after_fade.set(AfterFade::Nothing);
+                self.schedule_reconnect();
+            }
+            PwThreadMessage::Reconnect => self.reconnect(),
+            PwThreadMessage::Terminate { .. } => {
+                let _ = self.reconnect_timer.timer.update_timer(None, None);
+                self.mainloop.quit();
+            }
+            PwThreadMessage::Pause => {
+                if !shared.corked.get() {
+                    shared.active.set(false);
+                }
+            }
+            PwThreadMessage::Unpause => {
+                shared.corked.set(false);
+                shared.active.set(true);
+            }
+            PwThreadMessage::SetFmt { format, state } => {
+                shared.active.set(state == PlaybackState::Playing);
+                self.primary.borrow().data.fmt.set(format);
+            }
+            PwThreadMessage::SetVol { newvol } => shared.device_volume.save(newvol),
+            PwThreadMessage::TrackPosition { length, position } => shared.set_track_position(length, position),
+            // Reconnecting creates the stream from scratch anyway
+            PwThreadMessage::Reconfigure { config, .. } => shared.set_config(config),
+            _ => {}
+        }
+    }
+
+    fn schedule_reconnect(&self) {
+        let attempt = self.reconnect_attempts.get();
+        if attempt >= MAX_RECONNECT_ATTEMPTS {
+            DeadBeef::log_detailed(
+                DDB_LOG_LAYER_DEFAULT,
+                "Pipewire: Unable to reconnect to the daemon, giving up.\n",
+            );
+            self.shared.report(PwThreadEvent::Failed("Lost connection to the daemon".into()));
+            return;
+        }
+        self.reconnect_attempts.set(attempt + 1);
+
+        // 250ms doubling up to 4s between attempts
+        let delay = Duration::from_millis(250 << attempt.min(4));
+        if let Err(e) = self.reconnect_timer.timer.update_timer(Some(delay), None).into_result() {
+            let e = PluginError::from(pipewire::Error::from(e));
+            e.log("Unable to schedule reconnect");
+            self.shared.report(PwThreadEvent::Failed(e.to_string()));
+        }
+    }
+
+    /// Set up a new session and recreate the stream with the last format and playback state.
+    fn reconnect(&self) {
+        let shared = &self.shared;
+        let session = match Session::connect(&self.context, shared) {
+            Ok(session) => session,
+            Err(e) => {
+                debug!("Reconnect failed: {e}");
+                self.schedule_reconnect();
+                return;
+            }
+        };
+
+        // Node and link ids from the old daemon mean nothing now
+        shared.device_volume.forget_graph();
+
+        match self.new_primary(&session.core) {
+            Ok(primary) => {
+                // The old stream belongs to the old core, drop it first
+                self.primary.replace(primary);
+                self.session.replace(session);
+                self.reconnect_attempts.set(0);
+                shared.core_lost.set(false);
+                shared.stats.reconnected();
+                DeadBeef::log_detailed(DDB_LOG_LAYER_INFO, "Pipewire: Reconnected to the daemon.\n");
+            }
+            Err(e) => {
+                debug!("Unable to recreate stream: {e}");
+                self.schedule_reconnect();
+            }
+        }
+    }
+
+    /// Create a stream to take over from the primary one, in the same format and
+    /// playback state, fading in.
+    fn new_primary(&self, core: &Core) -> Result<OutputStream, PluginError> {
+        let shared = &self.shared;
+        let format = self.primary.borrow().data.fmt.get();
+        self.serial.set(self.serial.get().wrapping_add(1));
+        let primary = OutputStream::new(core, self.serial.get(), format, shared.active.get(), shared)?;
+        let frames = shared.fade_frames(format);
+        primary.data.ramp.borrow_mut().fade_out(0);
+        primary.data.ramp.borrow_mut().fade_in(frames);
+        Ok(primary)
+    }
+
+    /// Replace the primary stream with a fresh one, leaving DeadBeef playing or paused.
+    fn rebuild(&self) {
+        // Already on the way out
+        if self.shared.after_fade.get() == AfterFade::Quit {
+            return;
+        }
+        self.retiring.take();
+        self.shared.after_fade.set(AfterFade::Nothing);
+        let primary = self.new_primary(&self.session.borrow().core);
+        match primary {
+            Ok(primary) => {
+                let old = self.primary.replace(primary);
+                old.data.ourdisconnect.set(true);
+            }
+            Err(e) => {
+                e.log("Unable to rebuild stream, stopping");
+                self.shared.report(PwThreadEvent::Failed(e.to_string()));
+            }
+        }
+    }
+
+    /// Move the stream to the configured sink by reconnecting it. A stream can't
+    /// drop its target again, so going back to the default sink takes a new one.
+    fn retarget(&self) {
+        let target = self.shared.config.borrow().soundcard.clone();
+        if target == "default" {
+            self.rebuild();
+            return;
+        }
+        if self.shared.after_fade.get() == AfterFade::Quit {
+            return;
+        }
+        let primary = self.primary.borrow();
+        let props = properties! {
+            *pipewire::keys::TARGET_OBJECT => target,
+        };
+        update_stream_props(&primary.stream, &props);
+        let format = primary.data.fmt.get();
+        if let Err(e) = primary.set_format(format, self.shared.active.get(), &self.shared) {
+            e.log("Unable to reconnect stream, stopping");
+            self.shared.report(PwThreadEvent::Failed(e.to_string()));
+        }
+    }
+
+    /// A stream that can't be started or stopped is no use, so stop playback.
+    fn set_active(&self, output: &OutputStream, active: bool) {
+        if let Err(e) = output.stream.set_active(active) {
+            let e = PluginError::from(e);
+            e.log("Unable to change stream state, stopping");
+            self.shared.report(PwThreadEvent::Failed(e.to_string()));
+        }
+    }
+
+    /// Bring the output back up after a skip dip, at the new position.
+    fn skip_end(&self) {
+        let primary = self.primary.borrow();
+        primary.drop_tail();
+        let fading_out = primary.data.ramp.borrow().is_fading_out();
+        if self.shared.after_fade.get() == AfterFade::Nothing && fading_out {
+            let frames = self.shared.fade_frames(primary.data.fmt.get());
+            primary.data.ramp.borrow_mut().fade_in(frames);
+        }
+    }
+
+    /// Open a new primary stream in `format` and fade the current one out underneath it.
+    /// Each stream keeps its own format, so this works across sample rate changes.
+    fn crossfade(&self, format: WaveFormat) {
+        self.serial.set(self.serial.get().wrapping_add(1));
+        let session = self.session.borrow();
+        match OutputStream::new(&session.core, self.serial.get(), format, true, &self.shared) {
+            Ok(next) => {
+                let frames = self.shared.crossfade_frames(format);
+                next.data.ramp.borrow_mut().fade_out(0);
+                next.data.ramp.borrow_mut().fade_in(frames);
+
+                let old = self.primary.replace(next);
+                old.retire(self.shared.crossfade_frames(old.data.fmt.get()));
+                // Replacing drops a stream still retiring from an earlier crossfade
+                self.retiring.replace(Some(old));
+            }
+            Err(e) => e.log("Unable to create stream for crossfade"),
+        }
+    }
+
+    /// Swap the stream fading in for a new one in `format`, fading in from the start.
+    /// The stream fading out is left alone.
+    fn restart_incoming(&self, format: WaveFormat) {
+        self.serial.set(self.serial.get().wrapping_add(1));
+        let session = self.session.borrow();
+        match OutputStream::new(&session.core, self.serial.get(), format, true, &self.shared) {
+            Ok(next) => {
+                next.data.ramp.borrow_mut().fade_out(0);
+                next.data.ramp.borrow_mut().fade_in(self.shared.crossfade_frames(format));
+                let old = self.primary.replace(next);
+                old.data.ourdisconnect.set(true);
+            }
+            Err(e) => {
+                e.log("Unable to connect stream, stopping");
+                self.shared.report(PwThreadEvent::Failed(e.to_string()));
+            }
+        }
+    }
+}
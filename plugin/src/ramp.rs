@@ -0,0 +1,237 @@
+use crate::{read_sample, write_sample, WaveFormat};
+
+/// Linear gain ramp applied to interleaved audio in the process callback.
+///
+/// The gain steps once per frame, so a fade always spans exactly the
+/// requested number of frames regardless of how buffers are sized.
+pub struct Ramp {
+    gain: f32,
+    target: f32,
+    step: f32,
+    frames_left: u32,
+}
+
+impl Ramp {
+    pub fn new() -> Self {
+        Self {
+            gain: 1.0,
+            target: 1.0,
+            step: 0.0,
+            frames_left: 0,
+        }
+    }
+
+    pub fn fade_in(&mut self, frames: u32) {
+        self.start(1.0, frames);
+    }
+
+    pub fn fade_out(&mut self, frames: u32) {
+        self.start(0.0, frames);
+    }
+
+    fn start(&mut self, target: f32, frames: u32) {
+        self.target = target;
+        if frames == 0 {
+            self.gain = target;
+            self.step = 0.0;
+            self.frames_left = 0;
+        } else {
+            self.step = (target - self.gain) / frames as f32;
+            self.frames_left = frames;
+        }
+    }
+
+    /// Faded out completely, nothing needs to be read from the streamer.
+    pub fn is_silent(&self) -> bool {
+        self.frames_left == 0 && self.target == 0.0
+    }
+
+    /// Fading out or already silent.
+    pub fn is_fading_out(&self) -> bool {
+        self.target == 0.0
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.frames_left > 0
+    }
+
+    /// Scale the whole frames in `data` by the ramp, advancing it one step per frame.
+    ///
+    /// Returns true if the ramp reached its target within `data`.
+    pub fn apply(&mut self, data: &mut [u8], fmt: &WaveFormat) -> bool {
+        if self.is_silent() {
+            data.fill(0);
+            return false;
+        }
+        if !self.is_running() {
+            return false;
+        }
+
+        let sample_size = fmt.bytes_per_sample();
+        let stride = fmt.bytes_per_frame();
+
+        for frame in data.chunks_exact_mut(stride) {
+            if !self.is_running() {
+                if self.is_silent() {
+                    frame.fill(0);
+                }
+                continue;
+            }
+            for sample in frame.chunks_exact_mut(sample_size) {
+                scale_sample(sample, fmt.is_float(), self.gain);
+            }
+            self.frames_left -= 1;
+            self.gain = if self.frames_left == 0 {
+                self.target
+            } else {
+                self.gain + self.step
+            };
+        }
+
+        !self.is_running()
+    }
+}
+
+fn scale_sample(sample: &mut [u8], is_float: bool, gain: f32) {
+    let v = read_sample(sample, is_float);
+    write_sample(sample, is_float, v * gain as f64);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn format(bps: u32, channels: u32, is_float: bool) -> WaveFormat {
+        WaveFormat::new(48000, bps, channels, is_float).unwrap()
+    }
+
+    fn encode(fmt: &WaveFormat, samples: &[f64]) -> Vec<u8> {
+        let mut data = Vec::new();
+        for &v in samples {
+            match (fmt.bytes_per_sample(), fmt.is_float()) {
+                (1, _) => data.push(v as i8 as u8),
+                (2, _) => data.extend((v as i16).to_le_bytes()),
+                (3, _) => data.extend(&(v as i32).to_le_bytes()[..3]),
+                (4, true) => data.extend((v as f32).to_le_bytes()),
+                (4, false) => data.extend((v as i32).to_le_bytes()),
+                _ => unreachable!(),
+            }
+        }
+        data
+    }
+
+    fn decode(fmt: &WaveFormat, data: &[u8]) -> Vec<f64> {
+        data.chunks_exact(fmt.bytes_per_sample())
+            .map(|s| match (s.len(), fmt.is_float()) {
+                (1, _) => s[0] as i8 as f64,
+                (2, _) => i16::from_le_bytes([s[0], s[1]]) as f64,
+                (3, _) => (i32::from_le_bytes([0, s[0], s[1], s[2]]) >> 8) as f64,
+                (4, true) => f32::from_le_bytes([s[0], s[1], s[2], s[3]]) as f64,
+                (4, false) => i32::from_le_bytes([s[0], s[1], s[2], s[3]]) as f64,
+                _ => unreachable!(),
+            })
+            .collect()
+    }
+
+    /// A ramp that has faded out completely, ready to fade back in.
+    fn silent() -> Ramp {
+        let mut ramp = Ramp::new();
+        ramp.fade_out(0);
+        ramp
+    }
+
+    #[test]
+    fn fade_in_every_format() {
+        let cases = [
+            (8, false, 100.0),
+            (16, false, 1000.0),
+            (24, false, -400000.0),
+            (32, false, 1000000.0),
+            (32, true, 0.5),
+        ];
+        for (bps, is_float, v) in cases {
+            let fmt = format(bps, 1, is_float);
+            let mut data = encode(&fmt, &[v; 6]);
+            let mut ramp = silent();
+            ramp.fade_in(4);
+            assert!(ramp.apply(&mut data, &fmt));
+
+            let expected = [0.0, v * 0.25, v * 0.5, v * 0.75, v, v];
+            let got = decode(&fmt, &data);
+            for (g, e) in got.iter().zip(expected) {
+                assert!((g - e).abs() < 1e-6, "{bps} bps: got {got:?}, expected {expected:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn gain_steps_per_frame_not_per_sample() {
+        let fmt = format(16, 2, false);
+        let mut data = encode(&fmt, &[1000.0; 8]);
+        let mut ramp = silent();
+        ramp.fade_in(4);
+        ramp.apply(&mut data, &fmt);
+        assert_eq!(decode(&fmt, &data), [0.0, 0.0, 250.0, 250.0, 500.0, 500.0, 750.0, 750.0]);
+    }
+
+    #[test]
+    fn split_buffers_match_a_single_buffer() {
+        let fmt = format(16, 2, false);
+        let input = encode(&fmt, &(0..40).map(|i| 3000.0 + i as f64 * 10.0).collect::<Vec<_>>());
+
+        let mut whole = input.clone();
+        let mut ramp = Ramp::new();
+        ramp.fade_out(7);
+        ramp.apply(&mut whole, &fmt);
+
+        let mut split = input;
+        let mut ramp = Ramp::new();
+        ramp.fade_out(7);
+        let mut rest = &mut split[..];
+        for frames in [1, 3, 2, 5, 9] {
+            let (chunk, tail) = rest.split_at_mut(fmt.frames_to_bytes(frames));
+            ramp.apply(chunk, &fmt);
+            rest = tail;
+        }
+
+        assert_eq!(decode(&fmt, &split), decode(&fmt, &whole));
+        // Silent from the frame the fade ends on
+        assert!(decode(&fmt, &whole)[14..].iter().all(|v| *v == 0.0));
+    }
+
+    #[test]
+    fn reports_completion_once() {
+        let fmt = format(16, 1, false);
+        let mut ramp = Ramp::new();
+        ramp.fade_out(4);
+        assert!(ramp.is_fading_out());
+
+        let mut data = encode(&fmt, &[1000.0; 3]);
+        assert!(!ramp.apply(&mut data, &fmt));
+        assert!(ramp.is_running());
+        assert!(!ramp.is_silent());
+
+        let mut data = encode(&fmt, &[1000.0; 3]);
+        assert!(ramp.apply(&mut data, &fmt));
+        assert!(ramp.is_silent());
+        assert_eq!(decode(&fmt, &data), [250.0, 0.0, 0.0]);
+
+        // Silent stays silent without reporting again
+        let mut data = encode(&fmt, &[1000.0; 3]);
+        assert!(!ramp.apply(&mut data, &fmt));
+        assert_eq!(decode(&fmt, &data), [0.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn zero_length_fades_jump_to_target() {
+        let fmt = format(16, 1, false);
+        let mut ramp = silent();
+        assert!(ramp.is_silent());
+
+        ramp.fade_in(0);
+        assert!(!ramp.is_running());
+        let mut data = encode(&fmt, &[1000.0; 2]);
+        assert!(!ramp.apply(&mut data, &fmt));
+        assert_eq!(decode(&fmt, &data), [1000.0, 1000.0]);
+    }
+}
@@ -0,0 +1,201 @@
+use crate::*;
+
+const POSITION_NAMES: &[(&str, u32)] = &[
+    ("MONO", libspa_sys::SPA_AUDIO_CHANNEL_MONO),
+    ("FL", libspa_sys::SPA_AUDIO_CHANNEL_FL),
+    ("FR", libspa_sys::SPA_AUDIO_CHANNEL_FR),
+    ("FC", libspa_sys::SPA_AUDIO_CHANNEL_FC),
+    ("LFE", libspa_sys::SPA_AUDIO_CHANNEL_LFE),
+    ("SL", libspa_sys::SPA_AUDIO_CHANNEL_SL),
+    ("SR", libspa_sys::SPA_AUDIO_CHANNEL_SR),
+    ("FLC", libspa_sys::SPA_AUDIO_CHANNEL_FLC),
+    ("FRC", libspa_sys::SPA_AUDIO_CHANNEL_FRC),
+    ("RC", libspa_sys::SPA_AUDIO_CHANNEL_RC),
+    ("RL", libspa_sys::SPA_AUDIO_CHANNEL_RL),
+    ("RR", libspa_sys::SPA_AUDIO_CHANNEL_RR),
+    ("TC", libspa_sys::SPA_AUDIO_CHANNEL_TC),
+    ("TFL", libspa_sys::SPA_AUDIO_CHANNEL_TFL),
+    ("TFC", libspa_sys::SPA_AUDIO_CHANNEL_TFC),
+    ("TFR", libspa_sys::SPA_AUDIO_CHANNEL_TFR),
+    ("TRL", libspa_sys::SPA_AUDIO_CHANNEL_TRL),
+    ("TRC", libspa_sys::SPA_AUDIO_CHANNEL_TRC),
+    ("TRR", libspa_sys::SPA_AUDIO_CHANNEL_TRR),
+];
+
+/// User configurable remix applied to every frame before it goes out.
+///
+/// The config string lists where source channels go, for example `FL=FR;FR=FL`
+/// swaps left and right and `FC=FL:0.7,FR:0.7` folds the center into the fronts.
+/// Channels are named after their position in the stream's layout or given by index.
+/// Sources that aren't listed pass through unchanged, an empty list mutes the source.
+pub struct ChannelMatrix {
+    channels: usize,
+    /// Gain from source to destination, indexed `src * channels + dst`.
+    gains: Vec<f64>,
+    identity: bool,
+}
+
+impl ChannelMatrix {
+    pub fn identity(channels: usize) -> Self {
+        let mut gains = vec![0.0; channels * channels];
+        for c in 0..channels {
+            gains[c * channels + c] = 1.0;
+        }
+        Self {
+            channels,
+            gains,
+            identity: true,
+        }
+    }
+
+    /// Parse `spec` and validate it against a stream with `channels` channels.
+    pub fn parse(spec: &str, channels: u32) -> Result<Self, String> {
+        let positions = make_channel_map(channels);
+        let channels = channels as usize;
+        if channels > positions.len() {
+            return Err(format!("{channels} channels is more than we can remap"));
+        }
+
+        let index_of = |name: &str| -> Result<usize, String> {
+            if let Ok(i) = name.parse::<usize>() {
+                return if i < channels {
+                    Ok(i)
+                } else {
+                    Err(format!("channel {i} is out of range for {channels} channels"))
+                };
+            }
+            let position = POSITION_NAMES
+                .iter()
+                .find(|(n, _)| n.eq_ignore_ascii_case(name))
+                .map(|(_, p)| *p)
+                .ok_or_else(|| format!("unknown channel {name}"))?;
+            positions[..channels]
+                .iter()
+                .position(|p| *p == position)
+                .ok_or_else(|| format!("{name} is not part of the {channels} channel layout"))
+        };
+
+        let mut matrix = Self::identity(channels);
+        for entry in spec.split(';').map(str::trim).filter(|e| !e.is_empty()) {
+            let (src, dsts) = entry
+                .split_once('=')
+                .ok_or_else(|| format!("missing '=' in {entry}"))?;
+            let src = index_of(src.trim())?;
+
+            // A listed source only goes where it says
+            matrix.gains[src * channels..(src + 1) * channels].fill(0.0);
+            for dst in dsts.split(',').map(str::trim).filter(|d| !d.is_empty()) {
+                let (dst, gain) = match dst.split_once(':') {
+                    Some((dst, gain)) => (
+                        dst.trim(),
+                        gain.trim()
+                            .parse::<f64>()
+                            .map_err(|_| format!("bad gain {gain} in {entry}"))?,
+                    ),
+                    None => (dst, 1.0),
+                };
+                let dst = index_of(dst)?;
+                matrix.gains[src * channels + dst] += gain;
+            }
+            matrix.identity = false;
+        }
+        Ok(matrix)
+    }
+
+    pub fn apply(&self, data: &mut [u8], fmt: &WaveFormat) {
+        if self.identity || fmt.channels() as usize != self.channels {
+            return;
+        }
+        let sample_size = fmt.bytes_per_sample();
+        let stride = fmt.bytes_per_frame();
+        let is_float = fmt.is_float();
+
+        let mut input = [0.0; 64];
+        for frame in data.chunks_exact_mut(stride) {
+            for (i, sample) in frame.chunks_exact(sample_size).enumerate() {
+                input[i] = read_sample(sample, is_float);
+            }
+            for (dst, sample) in frame.chunks_exact_mut(sample_size).enumerate() {
+                let v = (0..self.channels)
+                    .map(|src| input[src] * self.gains[src * self.channels + dst])
+                    .sum();
+                write_sample(sample, is_float, v);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn format(channels: u32) -> WaveFormat {
+        WaveFormat::new(48000, 16, channels, false).unwrap()
+    }
+
+    fn encode(samples: &[i16]) -> Vec<u8> {
+        samples.iter().flat_map(|v| v.to_le_bytes()).collect()
+    }
+
+    fn decode(data: &[u8]) -> Vec<i16> {
+        data.chunks_exact(2).map(|s| i16::from_le_bytes([s[0], s[1]])).collect()
+    }
+
+    fn remap(spec: &str, channels: u32, samples: &[i16]) -> Vec<i16> {
+        let matrix = ChannelMatrix::parse(spec, channels).unwrap();
+        let mut data = encode(samples);
+        matrix.apply(&mut data, &format(channels));
+        decode(&data)
+    }
+
+    #[test]
+    fn empty_spec_is_identity() {
+        let matrix = ChannelMatrix::parse(" ; ", 2).unwrap();
+        assert!(matrix.identity);
+        assert_eq!(remap("", 2, &[100, -200, 300, -400]), [100, -200, 300, -400]);
+    }
+
+    #[test]
+    fn swaps_by_name_and_index() {
+        assert_eq!(remap("FL=FR;FR=FL", 2, &[100, 200, 300, 400]), [200, 100, 400, 300]);
+        assert_eq!(remap("0=1; 1=0", 2, &[100, 200]), [200, 100]);
+        assert_eq!(remap("fl=fr;fr=fl", 2, &[100, 200]), [200, 100]);
+    }
+
+    #[test]
+    fn folds_center_into_fronts() {
+        let got = remap("FC=FL:0.5,FR:0.5", 3, &[100, 200, 1000]);
+        assert_eq!(got, [600, 700, 0]);
+    }
+
+    #[test]
+    fn downmixes_to_mono_and_mutes() {
+        assert_eq!(remap("FL=FL:0.5,FR:0.5;FR=FL:0.5,FR:0.5", 2, &[100, 300]), [200, 200]);
+        assert_eq!(remap("FR=", 2, &[100, 300]), [100, 0]);
+    }
+
+    #[test]
+    fn rejects_malformed_specs() {
+        for spec in ["FL", "FL=FR:loud", "XX=FL", "FL=XX", "=FL"] {
+            assert!(ChannelMatrix::parse(spec, 2).is_err(), "{spec} was accepted");
+        }
+    }
+
+    #[test]
+    fn rejects_channels_outside_the_layout() {
+        assert!(ChannelMatrix::parse("2=0", 2).is_err());
+        assert!(ChannelMatrix::parse("0=2", 2).is_err());
+        // Stereo has no center
+        assert!(ChannelMatrix::parse("FC=FL", 2).is_err());
+        assert!(ChannelMatrix::parse("FL=FR", 1).is_err());
+        assert!(ChannelMatrix::parse("", 65).is_err());
+    }
+
+    #[test]
+    fn leaves_other_channel_counts_alone() {
+        let matrix = ChannelMatrix::parse("FL=FR;FR=FL", 2).unwrap();
+        let mut data = encode(&[100, 200, 300]);
+        matrix.apply(&mut data, &format(3));
+        assert_eq!(decode(&data), [100, 200, 300]);
+    }
+}
@@ -0,0 +1,27 @@
+/// Decode one little endian sample of `sample.len()` bytes.
+pub fn read_sample(sample: &[u8], is_float: bool) -> f64 {
+    match sample.len() {
+        1 => sample[0] as i8 as f64,
+        2 => i16::from_le_bytes([sample[0], sample[1]]) as f64,
+        3 => (i32::from_le_bytes([0, sample[0], sample[1], sample[2]]) >> 8) as f64,
+        4 if is_float => f32::from_le_bytes([sample[0], sample[1], sample[2], sample[3]]) as f64,
+        4 => i32::from_le_bytes([sample[0], sample[1], sample[2], sample[3]]) as f64,
+        _ => 0.0,
+    }
+}
+
+/// Encode `v` into `sample`. Float to int casts saturate, so out of range
+/// values clip instead of wrapping.
+pub fn write_sample(sample: &mut [u8], is_float: bool, v: f64) {
+    match sample.len() {
+        1 => sample[0] = v as i8 as u8,
+        2 => sample.copy_from_slice(&(v as i16).to_le_bytes()),
+        3 => {
+            let v = v.clamp(-8388608.0, 8388607.0) as i32;
+            sample.copy_from_slice(&v.to_le_bytes()[..3]);
+        }
+        4 if is_float => sample.copy_from_slice(&(v as f32).to_le_bytes()),
+        4 => sample.copy_from_slice(&(v as i32).to_le_bytes()),
+        _ => {}
+    }
+}
@@ -0,0 +1,70 @@
+use crate::*;
+
+use std::sync::atomic::{AtomicU8, Ordering};
+
+/// Life cycle of the output as driven by DeadBeef and reported by the playback thread.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum OutputState {
+    /// No playback thread.
+    Stopped,
+    /// The playback thread is up, waiting to be told to play.
+    Starting,
+    Playing,
+    Paused,
+    /// Fading out and shutting the playback thread down.
+    Draining,
+    /// The stream failed, nothing plays until DeadBeef starts over.
+    Error,
+}
+
+impl OutputState {
+    pub fn can_transition(self, next: Self) -> bool {
+        use OutputState::*;
+        match (self, next) {
+            // Anything can fail
+            (_, Error) => true,
+            // Pausing before anything is started only records the state
+            (Stopped, Paused) => true,
+            (Stopped | Paused | Error, Starting) => true,
+            (Starting, Playing | Paused | Draining) => true,
+            (Playing, Paused | Draining) => true,
+            (Paused, Playing | Draining) => true,
+            (Draining | Paused | Error, Stopped) => true,
+            _ => false,
+        }
+    }
+
+    /// The closest state DeadBeef knows about.
+    pub fn as_playback_state(self) -> PlaybackState {
+        match self {
+            Self::Playing => PlaybackState::Playing,
+            Self::Paused => PlaybackState::Paused,
+            Self::Stopped | Self::Starting | Self::Draining | Self::Error => PlaybackState::Stopped,
+        }
+    }
+}
+
+/// The current `OutputState`, readable from any thread without locking.
+pub struct StateCell(AtomicU8);
+
+impl StateCell {
+    pub fn new(state: OutputState) -> Self {
+        Self(AtomicU8::new(state as u8))
+    }
+
+    pub fn get(&self) -> OutputState {
+        match self.0.load(Ordering::Acquire) {
+            x if x == OutputState::Starting as u8 => OutputState::Starting,
+            x if x == OutputState::Playing as u8 => OutputState::Playing,
+            x if x == OutputState::Paused as u8 => OutputState::Paused,
+            x if x == OutputState::Draining as u8 => OutputState::Draining,
+            x if x == OutputState::Error as u8 => OutputState::Error,
+            _ => OutputState::Stopped,
+        }
+    }
+
+    pub fn set(&self, state: OutputState) {
+        self.0.store(state as u8, Ordering::Release);
+    }
+}
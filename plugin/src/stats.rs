@@ -0,0 +1,96 @@
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::time::Duration;
+
+/// Counters for one playback session. Updated from the PipeWire threads and read
+/// by the control thread when the user asks for diagnostics.
+#[derive(Default)]
+pub struct Stats {
+    underruns: AtomicU64,
+    short_reads: AtomicU64,
+    skipped_cycles: AtomicU64,
+    no_buffers: AtomicU64,
+    format_changes: AtomicU64,
+    reconnects: AtomicU64,
+    cycles: AtomicU64,
+    total_ns: AtomicU64,
+    max_ns: AtomicU64,
+    node_id: AtomicU32,
+    quantum: AtomicU32,
+    rate: AtomicU32,
+}
+
+impl Stats {
+    /// The streamer had nothing at all for us.
+    pub fn underrun(&self) {
+        self.underruns.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// The streamer had less than asked for.
+    pub fn short_read(&self) {
+        self.short_reads.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// A process cycle produced nothing, in the middle of a format change.
+    pub fn skipped_cycle(&self) {
+        self.skipped_cycles.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// A process cycle found no buffer to fill.
+    pub fn no_buffer(&self) {
+        self.no_buffers.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn format_changed(&self) {
+        self.format_changes.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn reconnected(&self) {
+        self.reconnects.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Time spent in one process callback.
+    pub fn cycle(&self, elapsed: Duration) {
+        let ns = elapsed.as_nanos() as u64;
+        self.cycles.fetch_add(1, Ordering::Relaxed);
+        self.total_ns.fetch_add(ns, Ordering::Relaxed);
+        self.max_ns.fetch_max(ns, Ordering::Relaxed);
+    }
+
+    pub fn set_node_id(&self, id: u32) {
+        self.node_id.store(id, Ordering::Relaxed);
+    }
+
+    /// Frames per cycle and graph rate as last seen by the process callback.
+    pub fn set_quantum(&self, quantum: u32) {
+        self.quantum.store(quantum, Ordering::Relaxed);
+    }
+
+    pub fn set_rate(&self, rate: u32) {
+        self.rate.store(rate, Ordering::Relaxed);
+    }
+
+    /// One line per group of counters, for the log.
+    pub fn report(&self) -> String {
+        let get = |v: &AtomicU64| v.load(Ordering::Relaxed);
+        let cycles = get(&self.cycles);
+        let avg_us = get(&self.total_ns) / cycles.max(1) / 1000;
+        format!(
+            "Node {}, quantum {} at {} Hz\n\
+             {} cycles, callback avg {} us, max {} us\n\
+             {} underruns, {} short reads, {} skipped cycles, {} without a buffer\n\
+             {} format changes, {} reconnects\n",
+            self.node_id.load(Ordering::Relaxed),
+            self.quantum.load(Ordering::Relaxed),
+            self.rate.load(Ordering::Relaxed),
+            cycles,
+            avg_us,
+            get(&self.max_ns) / 1000,
+            get(&self.underruns),
+            get(&self.short_reads),
+            get(&self.skipped_cycles),
+            get(&self.no_buffers),
+            get(&self.format_changes),
+            get(&self.reconnects),
+        )
+    }
+}
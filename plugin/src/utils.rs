@@ -1,13 +1,33 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
 use pipewire::{properties::Properties, spa::param::audio::AudioFormat};
 
-use crate::ddb_waveformat_t;
+use crate::{DeadBeef, WaveFormat, DDB_LOG_LAYER_INFO};
+
+/// Title format for the stream's `media.name`.
+pub const MEDIA_NAME_FORMAT: &str = "[%artist% - ]%title%";
+
+/// Mirrors the `pipewirerust_verbose` setting so checking it costs nothing.
+static VERBOSE: AtomicBool = AtomicBool::new(false);
+
+pub fn set_verbose(verbose: bool) {
+    VERBOSE.store(verbose, Ordering::Relaxed);
+}
+
+pub fn verbose() -> bool {
+    VERBOSE.load(Ordering::Relaxed)
+}
+
+pub fn log_verbose(msg: &str) {
+    DeadBeef::log_detailed(DDB_LOG_LAYER_INFO, format!("Pipewire: {msg}\n").as_str());
+}
 
-pub fn db_format_to_pipewire(input: ddb_waveformat_t) -> AudioFormat {
-    match input.bps {
+pub fn db_format_to_pipewire(input: WaveFormat) -> AudioFormat {
+    match input.bps() {
         8 => AudioFormat::S8,
         16 => AudioFormat::S16LE,
         24 => AudioFormat::S24LE,
-        32 => match input.is_float == 1 {
+        32 => match input.is_float() {
             true => AudioFormat::F32LE,
             false => AudioFormat::S32LE,
         },
@@ -15,22 +35,12 @@ pub fn db_format_to_pipewire(input: ddb_waveformat_t) -> AudioFormat {
     }
 }
 
-#[cfg(debug_assertions)]
-pub fn print_db_format(input: ddb_waveformat_t) {
-    println!(
-        "db format: {} bps{}, {} channels, {} kHz",
-        input.bps,
-        if input.is_float == 1 { " float" } else { "" },
-        input.channels,
-        input.samplerate
-    );
+pub fn print_db_format(input: WaveFormat) {
+    debug!("db format: {input}");
 }
-#[cfg(not(debug_assertions))]
-pub fn print_db_format(_input: ddb_waveformat_t) {}
 
-#[cfg(debug_assertions)]
 pub fn print_pipewire_format(format: AudioFormat, channels: u32, rate: u32) {
-    println!(
+    debug!(
         "pw format: {}, {} channels, {} kHz",
         match format {
             AudioFormat::S8 => "8 bps",
@@ -45,21 +55,6 @@ pub fn print_pipewire_format(format: AudioFormat, channels: u32, rate: u32) {
     );
 }
 
-#[cfg(not(debug_assertions))]
-pub fn print_pipewire_format(_format: AudioFormat, _channels: u32, _rate: u32) {}
-
-
-// Standalone function for this instead of "impl Default" because ddb_waveformat_t is generated by bindgen
-pub fn get_default_waveformat() -> ddb_waveformat_t {
-    ddb_waveformat_t {
-        samplerate: 44100,
-        bps: 16,
-        channels: 2,
-        channelmask: 3,
-        flags: 0,
-        is_float: 0,
-    }
-}
 
 pub fn update_stream_props(stream: &pipewire::stream::Stream, props: &Properties) {
     unsafe {
@@ -67,11 +62,11 @@ pub fn update_stream_props(stream: &pipewire::stream::Stream, props: &Properties
     }
 }
 
+/// Verbose log line, only formatted when `pipewirerust_verbose` is on.
 macro_rules! debug {
-    ($s:expr) => {
-        {
-        #[cfg(debug_assertions)]
-        eprintln!($s)
+    ($($arg:tt)*) => {
+        if $crate::utils::verbose() {
+            $crate::utils::log_verbose(&format!($($arg)*));
         }
     };
 }